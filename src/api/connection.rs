@@ -0,0 +1,101 @@
+use std::{fmt::Display, str::FromStr};
+
+use juniper::graphql_object;
+
+use crate::store::Seek;
+
+const DEFAULT_PAGE_SIZE: usize = 100;
+const MAX_PAGE_SIZE: usize = 1000;
+
+pub struct PageInfo {
+  pub has_previous_page: bool,
+  pub has_next_page: bool,
+  pub start_cursor: Option<String>,
+  pub end_cursor: Option<String>,
+}
+
+#[graphql_object(rename_all = "none")]
+impl PageInfo {
+  pub fn has_previous_page(&self) -> bool {
+    self.has_previous_page
+  }
+
+  pub fn has_next_page(&self) -> bool {
+    self.has_next_page
+  }
+
+  pub fn start_cursor(&self) -> Option<String> {
+    self.start_cursor.clone()
+  }
+
+  pub fn end_cursor(&self) -> Option<String> {
+    self.end_cursor.clone()
+  }
+}
+
+pub struct Page<C, T> {
+  pub edges: Vec<(C, T)>,
+  pub page_info: PageInfo,
+}
+
+// Relay style pagination, see: https://relay.dev/graphql/connections.htm
+// `fetch` yields items ordered by cursor, starting right past the cursor of the given seek.
+pub fn paginate<C, T, I>(
+  first: Option<i32>,
+  after: Option<String>,
+  last: Option<i32>,
+  before: Option<String>,
+  fetch: impl FnOnce(Seek<C>) -> anyhow::Result<I>,
+) -> anyhow::Result<Page<C, T>>
+where
+  C: Copy + Ord + Display + FromStr,
+  <C as FromStr>::Err: Into<anyhow::Error>,
+  I: Iterator<Item = anyhow::Result<(C, T)>>,
+{
+  let parse_count = |count: i32| -> anyhow::Result<usize> {
+    if count < 0 || count as usize > MAX_PAGE_SIZE {
+      anyhow::bail!("page size must be between 0 and {}", MAX_PAGE_SIZE);
+    }
+    Ok(count as usize)
+  };
+  let after = after.map(|cursor| C::from_str(&cursor).map_err(Into::<anyhow::Error>::into)).transpose()?;
+  let before = before.map(|cursor| C::from_str(&cursor).map_err(Into::<anyhow::Error>::into)).transpose()?;
+
+  let (seek, end, count) = match (first, last) {
+    (Some(_), Some(_)) => anyhow::bail!("first and last cannot be combined"),
+    (first, None) => (after.map_or(Seek::First, Seek::After), before, first.map_or(Ok(DEFAULT_PAGE_SIZE), parse_count)?),
+    (None, Some(last)) => (before.map_or(Seek::Last, Seek::Before), after, parse_count(last)?),
+  };
+  let forward = matches!(seek, Seek::First | Seek::After(_));
+
+  let mut edges = Vec::new();
+  let mut has_more = false;
+  for item in fetch(seek)? {
+    let (cursor, node) = item?;
+    let past_end = match end {
+      Some(end) if forward => cursor >= end,
+      Some(end) => cursor <= end,
+      None => false,
+    };
+    if past_end {
+      break;
+    }
+    if edges.len() == count {
+      has_more = true;
+      break;
+    }
+    edges.push((cursor, node));
+  }
+  if !forward {
+    edges.reverse();
+  }
+
+  let page_info = PageInfo {
+    has_previous_page: if forward { after.is_some() } else { has_more },
+    has_next_page: if forward { has_more } else { before.is_some() },
+    start_cursor: edges.first().map(|(cursor, _)| cursor.to_string()),
+    end_cursor: edges.last().map(|(cursor, _)| cursor.to_string()),
+  };
+
+  Ok(Page { edges, page_info })
+}
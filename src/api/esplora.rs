@@ -0,0 +1,290 @@
+use std::{str::FromStr, sync::Arc};
+
+use bitcoin::{Address, Network, OutPoint, ScriptHash, Txid};
+use rocket::{http::Status, response::content::RawJson, routes, Route, State};
+use serde_json::{json, Value};
+use tokio::task::block_in_place;
+
+use crate::{api::{get_unsorted_txos, resolve_txos}, mempool::Mempool, script_util::parse_electrum_script_hash, store::{block::BlockStoreRead as _, history::HistoryStoreRead as _, script::ScriptStoreRead as _, txo::TXOStoreRead as _, BlockHeight, Seek, Store}};
+
+// Routes mirroring the Esplora HTTP API, see: https://github.com/Blockstream/esplora/blob/master/API.md
+pub fn esplora_routes() -> Vec<Route> {
+  routes![address, address_utxo, scripthash_utxo, tip_height, block_height, tx_outspend]
+}
+
+type EsploraResult<T> = Result<T, (Status, String)>;
+
+fn bad_request(e: impl Into<anyhow::Error>) -> (Status, String) {
+  (Status::BadRequest, format!("{:#}", e.into()))
+}
+
+fn internal_error(e: anyhow::Error) -> (Status, String) {
+  (Status::InternalServerError, format!("{:#}", e))
+}
+
+#[rocket::get("/address/<address>")]
+fn address(
+  address: &str,
+  store: &State<Arc<Store>>,
+  mempool: &State<Arc<Mempool>>,
+  network: &State<Network>,
+) -> EsploraResult<RawJson<String>> {
+  let script_hash = parse_address(address, *network.inner())?;
+  // the transaction count would miss transactions of the blocks the backfill has yet to fill in
+  if block_in_place(|| store.get_block_backfill()).map_err(internal_error)?.is_some() {
+    return Err((Status::ServiceUnavailable, "address stats are unavailable until the block backfill is done".to_string()));
+  }
+  let mut stats = block_in_place(|| address_stats(store, mempool, &script_hash)).map_err(internal_error)?;
+  stats["address"] = json!(address);
+  Ok(RawJson(stats.to_string()))
+}
+
+#[rocket::get("/address/<address>/utxo")]
+fn address_utxo(
+  address: &str,
+  store: &State<Arc<Store>>,
+  mempool: &State<Arc<Mempool>>,
+  network: &State<Network>,
+) -> EsploraResult<RawJson<String>> {
+  let script_hash = parse_address(address, *network.inner())?;
+  let utxos = block_in_place(|| utxos(store, mempool, &script_hash)).map_err(internal_error)?;
+  Ok(RawJson(utxos.to_string()))
+}
+
+#[rocket::get("/scripthash/<hash>/utxo")]
+fn scripthash_utxo(
+  hash: &str,
+  store: &State<Arc<Store>>,
+  mempool: &State<Arc<Mempool>>,
+) -> EsploraResult<RawJson<String>> {
+  // Esplora script hashes are Electrum's
+  let script_sha256 = parse_electrum_script_hash(hash).map_err(bad_request)?;
+  let script = block_in_place(|| store.get_script_by_sha256(&script_sha256)).map_err(internal_error)?;
+  let script_hash = match script {
+    Some(script) => Some(script.script_hash()),
+    None => mempool.get_script_hash_by_sha256(&script_sha256),
+  };
+  let Some(script_hash) = script_hash else {
+    return Ok(RawJson("[]".to_string()));
+  };
+  let utxos = block_in_place(|| utxos(store, mempool, &script_hash)).map_err(internal_error)?;
+  Ok(RawJson(utxos.to_string()))
+}
+
+#[rocket::get("/blocks/tip/height")]
+fn tip_height(store: &State<Arc<Store>>) -> EsploraResult<String> {
+  let Some((height, _)) = block_in_place(|| store.get_tip_block()).map_err(internal_error)? else {
+    return Err((Status::NotFound, "no blocks are scanned yet".to_string()));
+  };
+  Ok(height.to_string())
+}
+
+#[rocket::get("/block-height/<height>")]
+fn block_height(height: BlockHeight, store: &State<Arc<Store>>) -> EsploraResult<String> {
+  let Some(block_hash) = block_in_place(|| store.get_block_hash(height)).map_err(internal_error)? else {
+    return Err((Status::NotFound, "Block not found".to_string()));
+  };
+  Ok(block_hash.to_string())
+}
+
+// spends by unconfirmed transactions are not reported, the mempool does not track their spenders
+#[rocket::get("/tx/<txid>/outspend/<vout>")]
+fn tx_outspend(txid: &str, vout: u32, store: &State<Arc<Store>>) -> EsploraResult<RawJson<String>> {
+  let outpoint = OutPoint {
+    txid: Txid::from_str(txid).map_err(bad_request)?,
+    vout,
+  };
+  let outspend = block_in_place(|| -> anyhow::Result<Value> {
+    let Some(txo) = get_unsorted_txos(store, &[outpoint])?.pop().flatten() else {
+      return Ok(json!({ "spent": false }));
+    };
+    let Some(spent_height) = txo.spent_height else {
+      return Ok(json!({ "spent": false }));
+    };
+    let mut outspend = json!({ "spent": true, "status": block_status(store, spent_height)? });
+    // TXOs spent before spenders were recorded lack them until backfilled
+    if let Some(spender) = txo.spender {
+      outspend["txid"] = json!(spender.txid.to_string());
+      outspend["vin"] = json!(spender.vin);
+    }
+    Ok(outspend)
+  }).map_err(internal_error)?;
+  Ok(RawJson(outspend.to_string()))
+}
+
+fn parse_address(address: &str, network: Network) -> EsploraResult<ScriptHash> {
+  let address = Address::from_str(address).map_err(bad_request)?.require_network(network).map_err(bad_request)?;
+  Ok(address.script_pubkey().script_hash())
+}
+
+fn address_stats(store: &Store, mempool: &Mempool, script_hash: &ScriptHash) -> anyhow::Result<Value> {
+  let (mut funded_txo_count, mut funded_txo_sum, mut spent_txo_count, mut spent_txo_sum) = (0, 0, 0, 0);
+  for txo in resolve_txos(store, store.get_locker_script_txos(script_hash, Seek::First)?) {
+    let (_, txo) = txo?;
+    funded_txo_count += 1;
+    funded_txo_sum += txo.value.to_sat();
+    if txo.spent_height.is_some() {
+      spent_txo_count += 1;
+      spent_txo_sum += txo.value.to_sat();
+    }
+  }
+
+  let overlay = mempool.get_script_overlay(store, script_hash)?.unwrap_or_default();
+
+  let tx_count = store.get_locker_script_transactions(script_hash, Seek::First)?.try_fold(0, |count, entry| entry.map(|_| count + 1))?;
+
+  Ok(json!({
+    "chain_stats": {
+      "funded_txo_count": funded_txo_count,
+      "funded_txo_sum": funded_txo_sum,
+      "spent_txo_count": spent_txo_count,
+      "spent_txo_sum": spent_txo_sum,
+      "tx_count": tx_count,
+    },
+    "mempool_stats": {
+      "funded_txo_count": overlay.generated_txos.len(),
+      "funded_txo_sum": overlay.generated_txos.iter().map(|(_, value)| value.to_sat()).sum::<u64>(),
+      "spent_txo_count": overlay.spent_txos.len(),
+      "spent_txo_sum": overlay.spent_txos.iter().map(|(_, value)| value.to_sat()).sum::<u64>(),
+      "tx_count": overlay.txids.len(),
+    },
+  }))
+}
+
+fn utxos(store: &Store, mempool: &Mempool, script_hash: &ScriptHash) -> anyhow::Result<Value> {
+  let utxos = mempool.get_script_utxos(store, script_hash)?.into_iter().map(|(outpoint, value, height)| -> anyhow::Result<Value> {
+    let status = match height {
+      Some(height) => block_status(store, height)?,
+      None => json!({ "confirmed": false }),
+    };
+    Ok(json!({
+      "txid": outpoint.txid.to_string(),
+      "vout": outpoint.vout,
+      "status": status,
+      "value": value.to_sat(),
+    }))
+  }).collect::<anyhow::Result<Vec<_>>>()?;
+  Ok(Value::Array(utxos))
+}
+
+fn block_status(store: &Store, height: BlockHeight) -> anyhow::Result<Value> {
+  let Some(block_hash) = store.get_block_hash(height)? else {
+    anyhow::bail!("missing block hash at height {}", height);
+  };
+  let mut status = json!({
+    "confirmed": true,
+    "block_height": height,
+    "block_hash": block_hash.to_string(),
+  });
+  // block times of data dirs scanned before they were stored are backfilled
+  if let Some(block_time) = store.get_block_time(height)? {
+    status["block_time"] = json!(block_time.time);
+  }
+  Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use bitcoin::{Address, Network};
+  use rocket::{http::Status, local::blocking::Client};
+  use serde_json::{json, Value};
+
+  use crate::{api::build, mempool::Mempool, scanner::events, store::{self, block::BlockStoreRead as _}, test_util::{coinbase, other_script, outpoint, script, tx, TestStore}};
+
+  fn address() -> String {
+    Address::from_script(&script(), Network::Regtest).unwrap().to_string()
+  }
+
+  fn client(test_store: &TestStore) -> Client {
+    Client::tracked(build(test_store.store().clone(), Arc::new(Mempool::default()), Network::Regtest, events::channel())).unwrap()
+  }
+
+  fn get_json(client: &Client, uri: &str) -> Value {
+    let response = client.get(uri).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    serde_json::from_str(&response.into_string().unwrap()).unwrap()
+  }
+
+  fn block_status(test_store: &TestStore, height: u32) -> Value {
+    let block_hash = test_store.store().get_block_hash(height).unwrap().unwrap();
+    json!({
+      "confirmed": true,
+      "block_height": height,
+      "block_hash": block_hash.to_string(),
+      "block_time": 1_231_006_505 + height * 600,
+    })
+  }
+
+  #[test]
+  fn address_utxo_lists_unspent_outputs() {
+    let test_store = TestStore::open("esplora-utxo");
+    let coinbase0 = coinbase(0, &[(script(), 50)]);
+    test_store.connect_block(vec![coinbase0.clone()]);
+    let coinbase1 = coinbase(1, &[(script(), 50)]);
+    let spending = tx(&[outpoint(&coinbase0, 0)], &[(other_script(), 50)]);
+    test_store.connect_block(vec![coinbase1.clone(), spending]);
+
+    let client = client(&test_store);
+    assert_eq!(get_json(&client, &format!("/address/{}/utxo", address())), json!([{
+      "txid": coinbase1.compute_txid().to_string(),
+      "vout": 0,
+      "status": block_status(&test_store, 1),
+      "value": 50,
+    }]));
+
+    let response = client.get("/address/not-an-address/utxo").dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+  }
+
+  #[test]
+  fn tx_outspend_reports_the_spender() {
+    let test_store = TestStore::open("esplora-outspend");
+    let coinbase0 = coinbase(0, &[(script(), 50), (other_script(), 25)]);
+    test_store.connect_block(vec![coinbase0.clone()]);
+    let spending = tx(&[outpoint(&coinbase0, 0)], &[(other_script(), 50)]);
+    test_store.connect_block(vec![coinbase(1, &[(other_script(), 50)]), spending.clone()]);
+
+    let client = client(&test_store);
+    let txid = coinbase0.compute_txid();
+    assert_eq!(get_json(&client, &format!("/tx/{}/outspend/0", txid)), json!({
+      "spent": true,
+      "status": block_status(&test_store, 1),
+      "txid": spending.compute_txid().to_string(),
+      "vin": 0,
+    }));
+    assert_eq!(get_json(&client, &format!("/tx/{}/outspend/1", txid)), json!({ "spent": false }));
+    assert_eq!(get_json(&client, &format!("/tx/{}/outspend/2", txid)), json!({ "spent": false }));
+  }
+
+  #[test]
+  fn address_is_unavailable_until_backfilled() {
+    let test_store = TestStore::open("esplora-address");
+    let coinbase0 = coinbase(0, &[(script(), 50)]);
+    test_store.connect_block(vec![coinbase0.clone()]);
+    // sent back to the address, one transaction funding and spending from it
+    test_store.connect_block(vec![coinbase(1, &[(other_script(), 50)]), tx(&[outpoint(&coinbase0, 0)], &[(script(), 50)])]);
+
+    let client = client(&test_store);
+    let stats = get_json(&client, &format!("/address/{}", address()));
+    assert_eq!(stats["address"], json!(address()));
+    assert_eq!(stats["chain_stats"], json!({
+      "funded_txo_count": 2,
+      "funded_txo_sum": 100,
+      "spent_txo_count": 1,
+      "spent_txo_sum": 50,
+      "tx_count": 2,
+    }));
+
+    let mut batch = store::Batch {
+      store: test_store.store(),
+      batch: rocksdb::WriteBatch::default(),
+    };
+    batch.set_block_backfill(Some(0..2));
+    batch.commit().unwrap();
+
+    let response = client.get(format!("/address/{}", address()).as_str()).dispatch();
+    assert_eq!(response.status(), Status::ServiceUnavailable);
+  }
+}
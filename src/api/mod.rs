@@ -1,24 +1,37 @@
-use std::{convert::Infallible, iter, str::FromStr, sync::Arc};
-use bitcoin::{Amount, ScriptBuf, ScriptHash};
-use juniper::{graphql_object, EmptyMutation, EmptySubscription, RootNode};
-use rocket::{response::content::RawHtml, routes, State};
+mod connection;
+mod esplora;
+mod subscription;
+
+use std::{collections::{HashMap, VecDeque}, convert::Infallible, fmt, iter, ops::RangeInclusive, str::FromStr, sync::Arc};
+use bitcoin::{Amount, BlockHash, Network, OutPoint, ScriptBuf, ScriptHash, SignedAmount, Txid};
+use chrono::{DateTime, Utc};
+use juniper::{graphql_object, EmptyMutation, GraphQLInputObject, RootNode};
+use rocket::{response::content::RawHtml, routes, Build, Rocket, State};
 use tokio::task::block_in_place;
 
-use crate::store::{block::BlockStoreRead, txo::{TXOState, TXOStoreRead}, BlockHeight, Store};
+use crate::{api::{connection::{paginate, PageInfo}, esplora::esplora_routes, subscription::{get_graphql, Subscription}}, mempool::Mempool, scanner::events::ScanEvents, script_util::{electrum_script_hash, parse_electrum_script_hash, script_address, script_type}, store::{balance::BalanceStoreRead as _, block::BlockStoreRead, history::HistoryStoreRead as _, script::ScriptStoreRead as _, tx::{TXLocation, TXStoreRead as _}, txo::{TXOSpender, TXOState, TXOStoreRead}, BlockHeight, Seek, Store}};
 
-pub async fn serve<'a>(store: Arc<Store>) -> anyhow::Result<Infallible> {
-  _ = rocket::build()
-    .manage(store)
-    .mount(
-      "/",
-      routes![graphiql, playground, post_graphql],
-    )
+pub async fn serve<'a>(store: Arc<Store>, mempool: Arc<Mempool>, network: Network, events: ScanEvents) -> anyhow::Result<Infallible> {
+  _ = build(store, mempool, network, events)
     .launch()
     .await?;
 
   unreachable!();
 }
 
+fn build(store: Arc<Store>, mempool: Arc<Mempool>, network: Network, events: ScanEvents) -> Rocket<Build> {
+  rocket::build()
+    .manage(store)
+    .manage(mempool)
+    .manage(network)
+    .manage(events)
+    .mount(
+      "/",
+      routes![graphiql, playground, post_graphql, get_graphql],
+    )
+    .mount("/", esplora_routes())
+}
+
 #[rocket::get("/graphiql")]
 fn graphiql() -> RawHtml<String> {
   juniper_rocket::graphiql_source("/graphql", None)
@@ -30,110 +43,154 @@ fn playground() -> RawHtml<String> {
 }
 
 #[rocket::post("/graphql", data = "<request>")]
-async fn post_graphql<'r>(
+async fn post_graphql(
   request: juniper_rocket::GraphQLRequest,
-  store: &'r State<Arc<Store>>,
+  store: &State<Arc<Store>>,
+  mempool: &State<Arc<Mempool>>,
+  network: &State<Network>,
+  events: &State<ScanEvents>,
 ) -> juniper_rocket::GraphQLResponse {
-  request.execute(&Schema::new(Query { store }, EmptyMutation::new(), EmptySubscription::new()), &()).await
+  request.execute(&schema(store, mempool, network, events), &()).await
 }
 
-type Schema<'r> = RootNode<Query<'r>, EmptyMutation<()>, EmptySubscription<()>>;
+// queries are served over HTTP and subscriptions over WebSocket, both on /graphql
+type Schema = RootNode<Query, EmptyMutation<()>, Subscription>;
 
-struct Query<'r> {
-  store: &'r Store,
+fn schema(store: &State<Arc<Store>>, mempool: &State<Arc<Mempool>>, network: &State<Network>, events: &State<ScanEvents>) -> Schema {
+  Schema::new(
+    Query { store: store.inner().clone(), mempool: mempool.inner().clone(), network: *network.inner() },
+    EmptyMutation::new(),
+    Subscription::new(*network.inner(), events.inner().clone()),
+  )
+}
+
+// owns what it reads from so the schema outlives the request upgraded to a WebSocket
+struct Query {
+  store: Arc<Store>,
+  mempool: Arc<Mempool>,
+  network: Network,
 }
 
 #[graphql_object(rename_all = "none")]
-impl<'r> Query<'r> {
+impl Query {
   async fn height(&self) -> anyhow::Result<i32> {
     Ok(block_in_place(||self.store.get_tip_block())?.map_or(0, |(height, _)| height as i32))
   }
 
-  async fn locker_script(&self, hex: Option<String>, address: Option<String>) -> anyhow::Result<ScriptObject> {
-    let script_bytes = match (hex, address) {
-      (Some(hex), None) => hex::decode(hex)?,
-      (None, Some(address)) => {
-        let address = bitcoin::Address::from_str(&address)?.require_network(bitcoin::Network::Bitcoin)?;
+  async fn height_at(&self, at: DateTime<Utc>) -> anyhow::Result<Option<String>> {
+    Ok(get_height_at(&self.store, at)?.map(|height| height.to_string()))
+  }
+
+  // null for an electrum script hash of a script no TXO is locked by, confirmed or not
+  async fn locker_script(&self, hex: Option<String>, address: Option<String>, electrum_script_hash: Option<String>) -> anyhow::Result<Option<ScriptObject>> {
+    let script_bytes = match (hex, address, electrum_script_hash) {
+      (Some(hex), None, None) => hex::decode(hex)?,
+      (None, Some(address), None) => {
+        let address = bitcoin::Address::from_str(&address)?.require_network(self.network)?;
         address.script_pubkey().into_bytes()
       }
-      _ => return Err(anyhow::anyhow!("either hex or address must be provided")),
+      (None, None, Some(electrum_script_hash)) => {
+        let script_sha256 = parse_electrum_script_hash(&electrum_script_hash)?;
+        // only scripts that locked a TXO are known by their hash
+        let script = match block_in_place(|| self.store.get_script_by_sha256(&script_sha256))? {
+          Some(script) => Some(script),
+          None => self.mempool.get_script_by_sha256(&script_sha256),
+        };
+        let Some(script) = script else {
+          return Ok(None);
+        };
+        script.into_bytes()
+      }
+      _ => return Err(anyhow::anyhow!("exactly one of hex, address or electrum_script_hash must be provided")),
     };
     let script = ScriptBuf::from_bytes(script_bytes.clone());
     let script_hash = script.script_hash();
-    Ok(ScriptObject { store: self.store, script_hash })
+    Ok(Some(ScriptObject { store: &self.store, mempool: &self.mempool, network: self.network, script, script_hash }))
   }
-}
 
-struct ScriptObject<'r> {
-  store: &'r Store,
-  script_hash: ScriptHash,
-}
+  async fn transaction(&self, txid: String) -> anyhow::Result<Option<Transaction>> {
+    let txid = Txid::from_str(&txid)?;
+    block_in_place(|| {
+      let Some(location) = self.store.get_tx_location(&txid)? else {
+        return Ok(None);
+      };
+      let Some(block_hash) = self.store.get_block_hash(location.height)? else {
+        anyhow::bail!("missing block hash at height {}", location.height);
+      };
 
-impl<'r> ScriptObject<'r> {
-  fn iterate_balance_history(&self) -> anyhow::Result<impl Iterator<Item = (BlockHeight, Amount)> + 'r> {
-    let txo_outpoints = block_in_place(||{
-      self.store.get_locker_script_txos(&self.script_hash)
-    })?.collect::<anyhow::Result<Vec<_>>>()?;
+      let outputs = self.store.get_tx_txos(&txid)?.collect::<anyhow::Result<Vec<_>>>()?;
 
-    let mut txos = block_in_place(||{
-      self.store.get_txos(txo_outpoints.iter())
-    })?.map(
-      |txo| {
-        let Some(txo) = txo? else {
-          anyhow::bail!("missing txo");
-        };
-        Ok(txo)
-      }
-    ).collect::<anyhow::Result<Vec<_>>>()?;
-    txos.sort_by_key(|txo|txo.generated_height);
+      let prevouts = self.store.get_spent_prevouts(&txid)?.collect::<anyhow::Result<Vec<_>>>()?;
+      let prevout_txos = get_unsorted_txos(&self.store, &prevouts.iter().map(|(_, prevout)| *prevout).collect::<Vec<_>>())?;
 
-    let mut spent_txos = txos.iter().filter(|txo|txo.spent_height.is_some()).copied().collect::<Vec<_>>();
-    spent_txos.sort_by_key(|txo|txo.spent_height.unwrap());
+      let scripts = get_locker_scripts(&self.store, outputs.iter().map(|(_, state)| state).chain(prevout_txos.iter().flatten()))?;
+      let outputs = outputs.into_iter().map(|(outpoint, state)| TXO::new(outpoint, state, &scripts, self.network)).collect();
+      let inputs = prevouts.into_iter().zip(prevout_txos).map(|((vin, prevout), txo)| TransactionInput {
+        vin,
+        prevout: txo.map(|state| TXO::new(prevout, state, &scripts, self.network)),
+        prevout_outpoint: prevout,
+      }).collect();
 
-    let mut txos = txos.into_iter().peekable();
-    let mut spent_txos = spent_txos.into_iter().peekable();
+      Ok(Some(Transaction { txid, location, block_hash, inputs, outputs }))
+    })
+  }
 
-    let mut balance = Amount::ZERO;
-    Ok(iter::from_fn(move || {
-      let next_generated_height = txos.peek().map(|txo|txo.generated_height);
-      let next_spent_height = spent_txos.peek().map(|txo|txo.spent_height.unwrap());
-      let next_height = match (next_generated_height, next_spent_height) {
-        (Some(generated_height), Some(spent_height)) => Some(generated_height.min(spent_height)),
-        (Some(generated_height), None) => Some(generated_height),
-        (None, Some(spent_height)) => Some(spent_height),
-        (None, None) => {
-          return None;
-        },
-      }?;
-
-      let prev_balance = balance;
-
-      while let Some(txo) = txos.peek() {
-        if txo.generated_height != next_height {
-          break;
-        }
-        let txo = txos.next().unwrap();
-        balance += txo.value;
-      }
-      while let Some(txo) = spent_txos.peek() {
-        if txo.spent_height.unwrap() != next_height {
-          break;
-        }
-        let txo = spent_txos.next().unwrap();
-        balance -= txo.value;
-      }
+  async fn txo(&self, txid: String, vout: i32) -> anyhow::Result<Option<TXO>> {
+    let outpoint = OutPointInput { txid, vout }.parse()?;
+    block_in_place(|| {
+      let Some(state) = get_unsorted_txos(&self.store, &[outpoint])?.pop().flatten() else {
+        return Ok(None);
+      };
+      let scripts = get_locker_scripts(&self.store, iter::once(&state))?;
+      Ok(Some(TXO::new(outpoint, state, &scripts, self.network)))
+    })
+  }
 
-      if balance == prev_balance {
-        return None;
-      }
+  async fn txos(&self, outpoints: Vec<OutPointInput>) -> anyhow::Result<Vec<Option<TXO>>> {
+    let max_outpoints = 1000;
+    if outpoints.len() > max_outpoints {
+      anyhow::bail!("at most {} outpoints can be looked up at once", max_outpoints);
+    }
+    let outpoints = outpoints.into_iter().map(OutPointInput::parse).collect::<anyhow::Result<Vec<_>>>()?;
+    let (txos, scripts) = block_in_place(|| {
+      let txos = get_unsorted_txos(&self.store, &outpoints)?;
+      let scripts = get_locker_scripts(&self.store, txos.iter().flatten())?;
+      Ok::<_, anyhow::Error>((txos, scripts))
+    })?;
+    Ok(outpoints.into_iter().zip(txos).map(|(outpoint, txo)| {
+      txo.map(|state| TXO::new(outpoint, state, &scripts, self.network))
+    }).collect())
+  }
+}
+
+#[derive(GraphQLInputObject)]
+#[graphql(rename_all = "none")]
+struct OutPointInput {
+  txid: String,
+  vout: i32,
+}
 
-      Some((next_height, balance))
-    }))
+impl OutPointInput {
+  fn parse(self) -> anyhow::Result<OutPoint> {
+    Ok(OutPoint {
+      txid: Txid::from_str(&self.txid)?,
+      vout: u32::try_from(self.vout)?,
+    })
   }
+}
 
+struct ScriptObject<'r> {
+  store: &'r Store,
+  mempool: &'r Mempool,
+  network: Network,
+  script: ScriptBuf,
+  script_hash: ScriptHash,
+}
+
+impl<'r> ScriptObject<'r> {
   fn gen_unspent_txos(&self) -> anyhow::Result<impl Iterator<Item = TXOState> + 'r> {
     let txo_outpoints = block_in_place(||{
-      self.store.get_locker_script_txos(&self.script_hash)
+      self.store.get_locker_script_unspent_txos(&self.script_hash, Seek::First)
     })?.collect::<anyhow::Result<Vec<_>>>()?;
     let unspent_txos = block_in_place(||{
       self.store.get_txos(txo_outpoints.iter())
@@ -142,15 +199,68 @@ impl<'r> ScriptObject<'r> {
         anyhow::bail!("missing txo");
       };
       Ok(txo)
-    }).filter(|txo|{
-      match txo {
-        Ok(txo) => txo.spent_height.is_none(),
-        _ => true,
-      }
     }).collect::<anyhow::Result<Vec<_>>>()?.into_iter();
     Ok(unspent_txos)
   }
 
+  // transactions funding or spending from the script with their net effect on it in block order, within `heights`
+  fn transaction_history(
+    &self,
+    seek: Seek<ScriptTransactionCursor>,
+    heights: RangeInclusive<BlockHeight>,
+  ) -> anyhow::Result<impl Iterator<Item = anyhow::Result<(ScriptTransactionCursor, (Txid, SignedAmount))>> + '_> {
+    // the scan starts within the heights rather than skipping up to them
+    let seek = match seek {
+      Seek::First | Seek::After(_) => {
+        let start = heights.start().checked_sub(1).map(|height| TXLocation { height, position: u32::MAX });
+        seek.cursor().map(|cursor| cursor.location).max(start).map_or(Seek::First, Seek::After)
+      }
+      Seek::Last | Seek::Before(_) => {
+        let end = heights.end().checked_add(1).map(|height| TXLocation { height, position: 0 });
+        match (seek.cursor().map(|cursor| cursor.location), end) {
+          (Some(cursor), Some(end)) => Seek::Before(cursor.min(end)),
+          (cursor, end) => cursor.or(end).map_or(Seek::Last, Seek::Before),
+        }
+      }
+    };
+
+    Ok(
+      self.store.get_locker_script_transactions(&self.script_hash, seek)?
+        .take_while(move |entry| match entry {
+          Ok((location, _, _)) => heights.contains(&location.height),
+          Err(_) => true,
+        })
+        .map(|entry| entry.map(|(location, txid, delta)| (ScriptTransactionCursor { location }, (txid, delta))))
+    )
+  }
+
+  fn txo_connection<'a, I: Iterator<Item = anyhow::Result<OutPoint>> + 'a>(
+    &'a self,
+    fetch_outpoints: impl FnOnce(Seek<OutPoint>) -> anyhow::Result<I>,
+    first: Option<i32>,
+    after: Option<String>,
+    last: Option<i32>,
+    before: Option<String>,
+    filter: TXOFilter,
+  ) -> anyhow::Result<TXOConnection> {
+    let page = paginate(first, after, last, before, |seek| {
+      Ok(resolve_txos(self.store, fetch_outpoints(seek)?).filter(move |txo| match txo {
+        Ok((_, state)) => filter.matches(state),
+        Err(_) => true,
+      }))
+    })?;
+
+    Ok(TXOConnection {
+      nodes: page.edges.into_iter().map(|(outpoint, state)| TXO {
+        outpoint,
+        state,
+        locker_script: Some(self.script.clone()),
+        network: self.network,
+      }).collect(),
+      page_info: page.page_info,
+    })
+  }
+
   fn recent_balance(&self) -> anyhow::Result<Amount> {
     let unspent_txos = self.gen_unspent_txos()?;
     let mut balance = Amount::ZERO;
@@ -163,11 +273,38 @@ impl<'r> ScriptObject<'r> {
 
 #[graphql_object(rename_all = "none")]
 impl<'r> ScriptObject<'r> {
-  async fn balance(&self, height: Option<String>) -> anyhow::Result<String> {
+  async fn hex(&self) -> String {
+    hex::encode(self.script.as_bytes())
+  }
+
+  async fn address(&self) -> Option<String> {
+    script_address(&self.script, self.network)
+  }
+
+  async fn electrum_script_hash(&self) -> String {
+    electrum_script_hash(&self.script)
+  }
+
+  #[graphql(name = "type")]
+  async fn script_type(&self) -> String {
+    script_type(&self.script).to_string()
+  }
+
+  // `at` resolves to the last block whose median time past is not after it
+  async fn balance(&self, height: Option<String>, at: Option<DateTime<Utc>>) -> anyhow::Result<String> {
+    let height = match (height, at) {
+      (Some(_), Some(_)) => anyhow::bail!("height and at cannot be combined"),
+      (Some(height), None) => Some(BlockHeight::from_str(&height)?),
+      (None, Some(at)) => match get_height_at(self.store, at)? {
+        Some(height) => Some(height),
+        // before the genesis block
+        None => return Ok(Amount::ZERO.to_sat().to_string()),
+      },
+      (None, None) => None,
+    };
+
     let balance = if let Some(height) = height {
-      let height = BlockHeight::from_str(&height)?;
-      let history = self.iterate_balance_history()?;
-      history.take_while(|(h, _)| *h <= height).map(|(_, balance)| balance).last().unwrap_or(Amount::ZERO)
+      block_in_place(|| self.store.get_balance_at(&self.script_hash, height))?
     } else {
       self.recent_balance()?
     };
@@ -175,14 +312,514 @@ impl<'r> ScriptObject<'r> {
     Ok(balance.to_sat().to_string())
   }
 
-  async fn balance_history(&self) -> anyhow::Result<Vec<HistoricalBalance>> {
-    Ok(self.iterate_balance_history()?.map(|(height, balance)| HistoricalBalance {
-      height,
-      balance,
+  async fn balance_history(
+    &self,
+    first: Option<i32>,
+    after: Option<String>,
+    last: Option<i32>,
+    before: Option<String>,
+  ) -> anyhow::Result<BalanceHistoryConnection> {
+    let page = block_in_place(|| paginate(first, after, last, before, |seek| {
+      self.store.get_balance_history(&self.script_hash, seek)
+    }))?;
+
+    Ok(BalanceHistoryConnection {
+      nodes: page.edges.into_iter().map(|(height, balance)| HistoricalBalance {
+        height,
+        balance,
+      }).collect(),
+      page_info: page.page_info,
+    })
+  }
+
+  async fn transactions(
+    &self,
+    first: Option<i32>,
+    after: Option<String>,
+    last: Option<i32>,
+    before: Option<String>,
+    from_height: Option<String>,
+    to_height: Option<String>,
+  ) -> anyhow::Result<ScriptTransactionConnection> {
+    let from_height = from_height.map(|height| BlockHeight::from_str(&height)).transpose()?.unwrap_or(BlockHeight::MIN);
+    let to_height = to_height.map(|height| BlockHeight::from_str(&height)).transpose()?.unwrap_or(BlockHeight::MAX);
+
+    let page = block_in_place(|| paginate(first, after, last, before, |seek| {
+      self.transaction_history(seek, from_height..=to_height)
+    }))?;
+
+    Ok(ScriptTransactionConnection {
+      nodes: page.edges.into_iter().map(|(cursor, (txid, delta))| ScriptTransaction {
+        location: cursor.location,
+        txid,
+        delta,
+      }).collect(),
+      page_info: page.page_info,
+    })
+  }
+
+  async fn utxos(
+    &self,
+    first: Option<i32>,
+    after: Option<String>,
+    last: Option<i32>,
+    before: Option<String>,
+    min_value: Option<String>,
+    min_confirmations: Option<i32>,
+  ) -> anyhow::Result<TXOConnection> {
+    let filter = TXOFilter::new(self.store, min_value, min_confirmations)?;
+    block_in_place(|| self.txo_connection(
+      |seek| self.store.get_locker_script_unspent_txos(&self.script_hash, seek),
+      first, after, last, before, filter,
+    ))
+  }
+
+  async fn txos(
+    &self,
+    first: Option<i32>,
+    after: Option<String>,
+    last: Option<i32>,
+    before: Option<String>,
+    min_value: Option<String>,
+    min_confirmations: Option<i32>,
+  ) -> anyhow::Result<TXOConnection> {
+    let filter = TXOFilter::new(self.store, min_value, min_confirmations)?;
+    block_in_place(|| self.txo_connection(
+      |seek| self.store.get_locker_script_txos(&self.script_hash, seek),
+      first, after, last, before, filter,
+    ))
+  }
+
+  async fn unconfirmed_balance(&self) -> anyhow::Result<String> {
+    let overlay = block_in_place(|| self.mempool.get_script_overlay(self.store, &self.script_hash))?;
+    Ok(overlay.map_or(0, |overlay| overlay.balance_delta()).to_string())
+  }
+
+  async fn unconfirmed_txos(&self) -> anyhow::Result<Vec<UnconfirmedTXO>> {
+    let Some(overlay) = block_in_place(|| self.mempool.get_script_overlay(self.store, &self.script_hash))? else {
+      return Ok(Vec::new());
+    };
+    Ok(overlay.generated_txos.iter().map(|(outpoint, value)| UnconfirmedTXO {
+      outpoint: *outpoint,
+      value: *value,
+      spent: overlay.is_spent(outpoint),
     }).collect())
   }
 }
 
+struct UnconfirmedTXO {
+  pub outpoint: OutPoint,
+  pub value: Amount,
+  pub spent: bool,
+}
+
+#[graphql_object(rename_all = "none")]
+impl UnconfirmedTXO {
+  pub fn txid(&self) -> String {
+    self.outpoint.txid.to_string()
+  }
+
+  pub fn vout(&self) -> i32 {
+    self.outpoint.vout as i32
+  }
+
+  pub fn value(&self) -> String {
+    self.value.to_sat().to_string()
+  }
+
+  // already spent by another unconfirmed transaction
+  pub fn spent(&self) -> bool {
+    self.spent
+  }
+}
+
+fn get_height_at(store: &Store, at: DateTime<Utc>) -> anyhow::Result<Option<BlockHeight>> {
+  let Ok(time) = u32::try_from(at.timestamp().max(0)) else {
+    // past the range of header timestamps
+    return Ok(block_in_place(|| store.get_tip_block())?.map(|(height, _)| height));
+  };
+  block_in_place(|| store.get_height_at_time(time))
+}
+
+// resolves the TXO states of an outpoint index scan, a chunk at a time
+fn resolve_txos<'a>(
+  store: &'a Store,
+  mut outpoints: impl Iterator<Item = anyhow::Result<OutPoint>> + 'a,
+) -> impl Iterator<Item = anyhow::Result<(OutPoint, TXOState)>> + 'a {
+  let chunk_size = 256;
+  let mut resolved = VecDeque::new();
+  iter::from_fn(move || {
+    if resolved.is_empty() {
+      let chunk = match outpoints.by_ref().take(chunk_size).collect::<anyhow::Result<Vec<_>>>() {
+        Ok(chunk) => chunk,
+        Err(e) => return Some(Err(e)),
+      };
+      match get_txo_states(store, chunk) {
+        Ok(txos) => resolved.extend(txos),
+        Err(e) => return Some(Err(e)),
+      }
+    }
+    resolved.pop_front().map(Ok)
+  })
+}
+
+// looks up TXO states in the given order, which need not be sorted
+fn get_unsorted_txos(store: &Store, outpoints: &[OutPoint]) -> anyhow::Result<Vec<Option<TXOState>>> {
+  let mut sorted_outpoints = outpoints.to_vec();
+  sorted_outpoints.sort();
+  sorted_outpoints.dedup();
+  let mut states = HashMap::new();
+  for (outpoint, txo) in sorted_outpoints.iter().zip(store.get_txos(sorted_outpoints.iter())?) {
+    if let Some(txo) = txo? {
+      states.insert(*outpoint, txo);
+    }
+  }
+  Ok(outpoints.iter().map(|outpoint| states.get(outpoint).copied()).collect())
+}
+
+// locker scripts of the given TXOs, TXOs scanned before scripts were stored may lack theirs until backfilled
+fn get_locker_scripts<'a>(store: &Store, states: impl Iterator<Item = &'a TXOState>) -> anyhow::Result<HashMap<ScriptHash, ScriptBuf>> {
+  let mut script_hashes = states.map(|state| state.locker_script_hash).collect::<Vec<_>>();
+  script_hashes.sort();
+  script_hashes.dedup();
+  let mut scripts = HashMap::new();
+  for (script_hash, script) in script_hashes.iter().zip(store.get_scripts(script_hashes.iter())?) {
+    if let Some(script) = script? {
+      scripts.insert(*script_hash, script);
+    }
+  }
+  Ok(scripts)
+}
+
+fn get_txo_states(store: &Store, outpoints: Vec<OutPoint>) -> anyhow::Result<Vec<(OutPoint, TXOState)>> {
+  let txos = get_unsorted_txos(store, &outpoints)?;
+  outpoints.into_iter().zip(txos).map(|(outpoint, txo)| {
+    let Some(txo) = txo else {
+      anyhow::bail!("missing txo {}", outpoint);
+    };
+    Ok((outpoint, txo))
+  }).collect()
+}
+
+#[derive(Clone, Copy)]
+struct TXOFilter {
+  min_value: Option<Amount>,
+  max_generated_height: Option<i64>,
+}
+
+impl TXOFilter {
+  fn new(store: &Store, min_value: Option<String>, min_confirmations: Option<i32>) -> anyhow::Result<Self> {
+    let min_value = min_value.map(|value| u64::from_str(&value)).transpose()?.map(Amount::from_sat);
+    let max_generated_height = match min_confirmations {
+      Some(min_confirmations) if min_confirmations > 0 => {
+        let tip_height = block_in_place(|| store.get_tip_block())?.map_or(-1, |(height, _)| height as i64);
+        // a TXO generated at the tip has one confirmation
+        Some(tip_height + 1 - min_confirmations as i64)
+      }
+      _ => None,
+    };
+    Ok(Self { min_value, max_generated_height })
+  }
+
+  fn matches(&self, state: &TXOState) -> bool {
+    self.min_value.map_or(true, |min_value| state.value >= min_value)
+      && self.max_generated_height.map_or(true, |max_height| state.generated_height as i64 <= max_height)
+  }
+}
+
+struct TXOConnection {
+  pub nodes: Vec<TXO>,
+  pub page_info: PageInfo,
+}
+
+#[graphql_object(rename_all = "none")]
+impl TXOConnection {
+  pub fn edges(&self) -> Vec<TXOEdge> {
+    self.nodes.iter().map(|node| TXOEdge { node }).collect()
+  }
+
+  pub fn nodes(&self) -> &[TXO] {
+    &self.nodes
+  }
+
+  pub fn page_info(&self) -> &PageInfo {
+    &self.page_info
+  }
+}
+
+struct TXOEdge<'a> {
+  pub node: &'a TXO,
+}
+
+#[graphql_object(rename_all = "none")]
+impl<'a> TXOEdge<'a> {
+  pub fn cursor(&self) -> String {
+    self.node.outpoint.to_string()
+  }
+
+  pub fn node(&self) -> &TXO {
+    self.node
+  }
+}
+
+struct TXO {
+  pub outpoint: OutPoint,
+  pub state: TXOState,
+  pub locker_script: Option<ScriptBuf>,
+  pub network: Network,
+}
+
+impl TXO {
+  fn new(outpoint: OutPoint, state: TXOState, scripts: &HashMap<ScriptHash, ScriptBuf>, network: Network) -> Self {
+    Self {
+      outpoint,
+      state,
+      locker_script: scripts.get(&state.locker_script_hash).cloned(),
+      network,
+    }
+  }
+}
+
+#[graphql_object(rename_all = "none")]
+impl TXO {
+  pub fn txid(&self) -> String {
+    self.outpoint.txid.to_string()
+  }
+
+  pub fn vout(&self) -> i32 {
+    self.outpoint.vout as i32
+  }
+
+  pub fn value(&self) -> String {
+    self.state.value.to_sat().to_string()
+  }
+
+  pub fn generated_height(&self) -> String {
+    self.state.generated_height.to_string()
+  }
+
+  pub fn spent_height(&self) -> Option<String> {
+    self.state.spent_height.map(|height| height.to_string())
+  }
+
+  pub fn spent_by(&self) -> Option<SpentBy> {
+    self.state.spender.map(|spender| SpentBy { spender })
+  }
+
+  pub fn locker_script_hash(&self) -> String {
+    self.state.locker_script_hash.to_string()
+  }
+
+  pub fn locker_script(&self) -> Option<String> {
+    self.locker_script.as_ref().map(|script| hex::encode(script.as_bytes()))
+  }
+
+  pub fn locker_script_type(&self) -> Option<String> {
+    self.locker_script.as_ref().map(|script| script_type(script).to_string())
+  }
+
+  pub fn locker_script_address(&self) -> Option<String> {
+    self.locker_script.as_ref().and_then(|script| script_address(script, self.network))
+  }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct ScriptTransactionCursor {
+  location: TXLocation,
+}
+
+impl fmt::Display for ScriptTransactionCursor {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}:{}", self.location.height, self.location.position)
+  }
+}
+
+impl FromStr for ScriptTransactionCursor {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let Some((height, position)) = s.split_once(':') else {
+      anyhow::bail!("invalid transaction cursor {}", s);
+    };
+    Ok(Self {
+      location: TXLocation {
+        height: BlockHeight::from_str(height)?,
+        position: u32::from_str(position)?,
+      },
+    })
+  }
+}
+
+struct ScriptTransactionConnection {
+  pub nodes: Vec<ScriptTransaction>,
+  pub page_info: PageInfo,
+}
+
+#[graphql_object(rename_all = "none")]
+impl ScriptTransactionConnection {
+  pub fn edges(&self) -> Vec<ScriptTransactionEdge> {
+    self.nodes.iter().map(|node| ScriptTransactionEdge { node }).collect()
+  }
+
+  pub fn nodes(&self) -> &[ScriptTransaction] {
+    &self.nodes
+  }
+
+  pub fn page_info(&self) -> &PageInfo {
+    &self.page_info
+  }
+}
+
+struct ScriptTransactionEdge<'a> {
+  pub node: &'a ScriptTransaction,
+}
+
+#[graphql_object(rename_all = "none")]
+impl<'a> ScriptTransactionEdge<'a> {
+  pub fn cursor(&self) -> String {
+    ScriptTransactionCursor {
+      location: self.node.location,
+    }.to_string()
+  }
+
+  pub fn node(&self) -> &ScriptTransaction {
+    self.node
+  }
+}
+
+struct ScriptTransaction {
+  pub location: TXLocation,
+  pub txid: Txid,
+  pub delta: SignedAmount,
+}
+
+#[graphql_object(rename_all = "none")]
+impl ScriptTransaction {
+  pub fn txid(&self) -> String {
+    self.txid.to_string()
+  }
+
+  pub fn height(&self) -> String {
+    self.location.height.to_string()
+  }
+
+  // net value received by the script, negative when it was spent from
+  pub fn delta(&self) -> String {
+    self.delta.to_sat().to_string()
+  }
+}
+
+struct Transaction {
+  pub txid: Txid,
+  pub location: TXLocation,
+  pub block_hash: BlockHash,
+  pub inputs: Vec<TransactionInput>,
+  pub outputs: Vec<TXO>,
+}
+
+#[graphql_object(rename_all = "none")]
+impl Transaction {
+  pub fn txid(&self) -> String {
+    self.txid.to_string()
+  }
+
+  pub fn height(&self) -> String {
+    self.location.height.to_string()
+  }
+
+  pub fn position(&self) -> i32 {
+    self.location.position as i32
+  }
+
+  pub fn block_hash(&self) -> String {
+    self.block_hash.to_string()
+  }
+
+  // coinbase inputs spend nothing and are left out
+  pub fn inputs(&self) -> &[TransactionInput] {
+    &self.inputs
+  }
+
+  pub fn outputs(&self) -> &[TXO] {
+    &self.outputs
+  }
+}
+
+struct TransactionInput {
+  pub vin: u32,
+  pub prevout_outpoint: OutPoint,
+  pub prevout: Option<TXO>,
+}
+
+#[graphql_object(rename_all = "none")]
+impl TransactionInput {
+  pub fn vin(&self) -> i32 {
+    self.vin as i32
+  }
+
+  pub fn prevout_txid(&self) -> String {
+    self.prevout_outpoint.txid.to_string()
+  }
+
+  pub fn prevout_vout(&self) -> i32 {
+    self.prevout_outpoint.vout as i32
+  }
+
+  pub fn prevout(&self) -> Option<&TXO> {
+    self.prevout.as_ref()
+  }
+}
+
+struct SpentBy {
+  pub spender: TXOSpender,
+}
+
+#[graphql_object(rename_all = "none")]
+impl SpentBy {
+  pub fn txid(&self) -> String {
+    self.spender.txid.to_string()
+  }
+
+  pub fn vin(&self) -> i32 {
+    self.spender.vin as i32
+  }
+}
+
+struct BalanceHistoryConnection {
+  pub nodes: Vec<HistoricalBalance>,
+  pub page_info: PageInfo,
+}
+
+#[graphql_object(rename_all = "none")]
+impl BalanceHistoryConnection {
+  pub fn edges(&self) -> Vec<HistoricalBalanceEdge> {
+    self.nodes.iter().map(|node| HistoricalBalanceEdge { node }).collect()
+  }
+
+  pub fn nodes(&self) -> &[HistoricalBalance] {
+    &self.nodes
+  }
+
+  pub fn page_info(&self) -> &PageInfo {
+    &self.page_info
+  }
+}
+
+struct HistoricalBalanceEdge<'a> {
+  pub node: &'a HistoricalBalance,
+}
+
+#[graphql_object(rename_all = "none")]
+impl<'a> HistoricalBalanceEdge<'a> {
+  pub fn cursor(&self) -> String {
+    self.node.height.to_string()
+  }
+
+  pub fn node(&self) -> &HistoricalBalance {
+    self.node
+  }
+}
+
 struct HistoricalBalance {
   pub height: BlockHeight,
   pub balance: Amount,
@@ -198,3 +835,128 @@ impl HistoricalBalance {
     self.balance.to_sat().to_string()
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use std::{collections::HashMap, sync::Arc};
+
+  use bitcoin::{Amount, Network, ScriptBuf, Txid};
+
+  use super::{Query, ScriptObject};
+  use crate::{mempool::Mempool, script_util::electrum_script_hash, store::{balance::BalanceStoreRead as _, BlockHeight, Store}, test_util::{coinbase, other_script, outpoint, script, tx, TestStore}};
+
+  async fn balance_history(store: &Store, first: Option<i32>, after: Option<&str>) -> (Vec<(BlockHeight, u64)>, bool) {
+    let mempool = Mempool::default();
+    let script = script();
+    let script_hash = script.script_hash();
+    let object = ScriptObject {
+      store,
+      mempool: &mempool,
+      network: Network::Regtest,
+      script,
+      script_hash,
+    };
+    let connection = object.balance_history(first, after.map(str::to_string), None, None).await.unwrap();
+    let history = connection.nodes.iter().map(|node| (node.height, node.balance.to_sat())).collect();
+    (history, connection.page_info.has_next_page)
+  }
+
+  async fn transactions(store: &Store, first: Option<i32>, after: Option<&str>, last: Option<i32>, from_height: Option<&str>) -> Vec<(BlockHeight, Txid, i64)> {
+    let mempool = Mempool::default();
+    let script = script();
+    let script_hash = script.script_hash();
+    let object = ScriptObject {
+      store,
+      mempool: &mempool,
+      network: Network::Regtest,
+      script,
+      script_hash,
+    };
+    let connection = object.transactions(first, after.map(str::to_string), last, None, from_height.map(str::to_string), None).await.unwrap();
+    connection.nodes.iter().map(|node| (node.location.height, node.txid, node.delta.to_sat())).collect()
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn balance_history_skips_self_spends() {
+    let test_store = TestStore::open("self-spends");
+    let coinbase0 = coinbase(0, &[(script(), 50)]);
+    test_store.connect_block(vec![coinbase0.clone()]);
+    // the whole balance is sent back to the script
+    test_store.connect_block(vec![coinbase(1, &[(other_script(), 50)]), tx(&[outpoint(&coinbase0, 0)], &[(script(), 50)])]);
+    test_store.connect_block(vec![coinbase(2, &[(script(), 10)])]);
+
+    assert_eq!(balance_history(test_store.store(), None, None).await.0, vec![(0, 50), (2, 60)]);
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn balance_history_skips_same_block_create_and_spend() {
+    let test_store = TestStore::open("same-block");
+    let coinbase0 = coinbase(0, &[(script(), 50)]);
+    test_store.connect_block(vec![coinbase0.clone()]);
+    let coinbase1 = coinbase(1, &[(other_script(), 50)]);
+    let funding = tx(&[outpoint(&coinbase1, 0)], &[(script(), 5)]);
+    let spending = tx(&[outpoint(&funding, 0)], &[(other_script(), 5)]);
+    test_store.connect_block(vec![coinbase1, funding, spending]);
+    test_store.connect_block(vec![coinbase(2, &[(other_script(), 50)]), tx(&[outpoint(&coinbase0, 0)], &[(other_script(), 50)])]);
+
+    assert_eq!(balance_history(test_store.store(), None, None).await.0, vec![(0, 50), (2, 0)]);
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn balance_history_counts_coinbase_outputs() {
+    let test_store = TestStore::open("coinbase");
+    let coinbase0 = coinbase(0, &[(script(), 50)]);
+    test_store.connect_block(vec![coinbase0.clone()]);
+    // the coinbase adds to a balance sent back to the script
+    let coinbase1 = coinbase(1, &[(script(), 50)]);
+    test_store.connect_block(vec![coinbase1.clone(), tx(&[outpoint(&coinbase0, 0)], &[(script(), 50)])]);
+    test_store.connect_block(vec![coinbase(2, &[(script(), 25), (other_script(), 25)])]);
+    // the coinbase makes up for an output spent elsewhere
+    test_store.connect_block(vec![coinbase(3, &[(script(), 50)]), tx(&[outpoint(&coinbase1, 0)], &[(other_script(), 50)])]);
+    test_store.connect_block(vec![coinbase(4, &[(script(), 10)])]);
+
+    assert_eq!(balance_history(test_store.store(), None, None).await.0, vec![(0, 50), (1, 100), (2, 125), (4, 135)]);
+    assert_eq!(balance_history(test_store.store(), Some(2), Some("0")).await, (vec![(1, 100), (2, 125)], true));
+    assert_eq!(balance_history(test_store.store(), Some(2), Some("2")).await, (vec![(4, 135)], false));
+    assert_eq!(test_store.store().get_balance_at(&script().script_hash(), 3).unwrap(), Amount::from_sat(125));
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn transactions_are_in_block_order() {
+    let test_store = TestStore::open("transactions");
+    let coinbase0 = coinbase(0, &[(script(), 50)]);
+    test_store.connect_block(vec![coinbase0.clone()]);
+    let coinbase1 = coinbase(1, &[(other_script(), 50)]);
+    let funding = tx(&[outpoint(&coinbase1, 0)], &[(script(), 20), (other_script(), 30)]);
+    // sent back to the script whole, still one of its transactions
+    let self_transfer = tx(&[outpoint(&coinbase0, 0)], &[(script(), 50)]);
+    test_store.connect_block(vec![coinbase1, funding.clone(), self_transfer.clone()]);
+
+    let store = test_store.store();
+    let history = vec![
+      (0, coinbase0.compute_txid(), 50),
+      (1, funding.compute_txid(), 20),
+      (1, self_transfer.compute_txid(), 0),
+    ];
+    assert_eq!(transactions(store, None, None, None, None).await, history);
+    assert_eq!(transactions(store, Some(1), Some("1:1"), None, None).await, history[2..]);
+    assert_eq!(transactions(store, None, None, Some(2), None).await, history[1..]);
+    assert_eq!(transactions(store, None, None, None, Some("1")).await, history[1..]);
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn locker_script_resolves_unconfirmed_electrum_script_hashes() {
+    let test_store = TestStore::open("electrum-script-hash");
+    let coinbase0 = coinbase(0, &[(other_script(), 50)]);
+    test_store.connect_block(vec![coinbase0.clone()]);
+    let unconfirmed = tx(&[outpoint(&coinbase0, 0)], &[(script(), 50)]);
+    let mempool = Mempool::default();
+    mempool.update(test_store.store(), &mut HashMap::from([(unconfirmed.compute_txid(), unconfirmed)])).unwrap();
+
+    let query = Query { store: test_store.store().clone(), mempool: Arc::new(mempool), network: Network::Regtest };
+    let object = query.locker_script(None, None, Some(electrum_script_hash(&script()))).await.unwrap().unwrap();
+    assert_eq!(object.script, script());
+    let unknown = ScriptBuf::from_bytes(vec![0x53]);
+    assert!(query.locker_script(None, None, Some(electrum_script_hash(&unknown))).await.unwrap().is_none());
+  }
+}
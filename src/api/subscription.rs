@@ -0,0 +1,430 @@
+use std::{collections::{BTreeMap, HashMap}, pin::Pin, str::FromStr, sync::Arc};
+
+use bitcoin::{Amount, BlockHash, Network, OutPoint, ScriptBuf, ScriptHash, SignedAmount, Txid};
+use futures::{SinkExt as _, Stream, StreamExt as _};
+use juniper::{graphql_object, graphql_subscription, DefaultScalarValue, FieldError, FieldResult};
+use juniper_graphql_ws::{graphql_transport_ws::{ClientMessage, Connection, Input, Output}, ConnectionConfig};
+use rocket::State;
+use rocket_ws::{frame::{CloseCode, CloseFrame}, Message, WebSocket};
+use tokio::{select, sync::broadcast::error::RecvError};
+
+use crate::{api::{schema, SpentBy}, mempool::Mempool, scanner::events::{ScanEvent, ScanEvents}, store::{txo::TXOSpender, BlockHeight, Store}};
+
+type EventStream<T> = Pin<Box<dyn Stream<Item = FieldResult<T>> + Send>>;
+
+// Subscriptions are served over WebSocket with the graphql-transport-ws protocol,
+// see: https://github.com/enisdenjo/graphql-ws/blob/master/PROTOCOL.md
+#[rocket::get("/graphql")]
+pub fn get_graphql(
+  ws: WebSocket,
+  store: &State<Arc<Store>>,
+  mempool: &State<Arc<Mempool>>,
+  network: &State<Network>,
+  events: &State<ScanEvents>,
+) -> rocket_ws::Channel<'static> {
+  let schema = Arc::new(schema(store, mempool, network, events));
+
+  ws.channel(move |stream| Box::pin(async move {
+    let (mut ws_sink, mut ws_stream) = stream.split();
+    let (mut connection_sink, mut connection_stream) = Connection::new(schema, ConnectionConfig::new(()))
+      .split::<Input<DefaultScalarValue>>();
+
+    let incoming = async move {
+      while let Some(message) = ws_stream.next().await {
+        let input = match message? {
+          Message::Text(text) => match serde_json::from_str::<ClientMessage<DefaultScalarValue>>(&text) {
+            Ok(message) => Input::Message(message),
+            Err(e) => {
+              tracing::debug!("invalid graphql-transport-ws message: {}", e);
+              Input::Close
+            }
+          },
+          Message::Close(_) => Input::Close,
+          _ => continue,
+        };
+        let close = matches!(input, Input::Close);
+        if connection_sink.send(input).await.is_err() || close {
+          break;
+        }
+      }
+      Ok(())
+    };
+
+    let outgoing = async move {
+      while let Some(output) = connection_stream.next().await {
+        match output {
+          Output::Message(message) => {
+            ws_sink.send(Message::Text(serde_json::to_string(&message).unwrap())).await?;
+          }
+          Output::Close { code, message } => {
+            ws_sink.send(Message::Close(Some(CloseFrame { code: CloseCode::from(code), reason: message.into() }))).await?;
+            break;
+          }
+        }
+      }
+      Ok(())
+    };
+
+    select! {
+      res = incoming => res,
+      res = outgoing => res,
+    }
+  }))
+}
+
+pub struct Subscription {
+  network: Network,
+  events: ScanEvents,
+}
+
+impl Subscription {
+  pub fn new(network: Network, events: ScanEvents) -> Self {
+    Self { network, events }
+  }
+}
+
+#[graphql_subscription(rename_all = "none")]
+impl Subscription {
+  async fn new_block(&self) -> EventStream<BlockEvent> {
+    subscribe(&self.events, |event| match event {
+      ScanEvent::Connected(connected) => connected.blocks.iter().map(|(height, block_hash)| BlockEvent {
+        height: *height,
+        block_hash: *block_hash,
+        connected: true,
+      }).collect(),
+      ScanEvent::Disconnected(rewound) => rewound.blocks.iter().map(|block| BlockEvent {
+        height: block.height,
+        block_hash: block.block_hash,
+        connected: false,
+      }).collect(),
+    })
+  }
+
+  async fn script_activity(&self, hex: Option<String>, address: Option<String>) -> FieldResult<EventStream<ScriptActivity>> {
+    let script = match (hex, address) {
+      (Some(hex), None) => ScriptBuf::from_bytes(hex::decode(hex)?),
+      (None, Some(address)) => bitcoin::Address::from_str(&address)?.require_network(self.network)?.script_pubkey(),
+      _ => return Err(FieldError::from("either hex or address must be provided")),
+    };
+    let script_hash = script.script_hash();
+    Ok(subscribe(&self.events, move |event| script_activities(event, &script_hash)))
+  }
+
+  async fn utxo_spent(&self, txid: String, vout: i32) -> FieldResult<EventStream<UTXOSpent>> {
+    let outpoint = OutPoint {
+      txid: Txid::from_str(&txid)?,
+      vout: u32::try_from(vout)?,
+    };
+    Ok(subscribe(&self.events, move |event| match event {
+      ScanEvent::Connected(connected) => connected.spent_txos.iter()
+        .filter(|(spent, _, _, _)| *spent == outpoint)
+        .map(|(_, txo, _, _)| UTXOSpent {
+          outpoint,
+          height: txo.spent_height,
+          block_hash: connected.blocks.iter().find(|(height, _)| *height == txo.spent_height).map(|(_, block_hash)| *block_hash),
+          connected: true,
+          spender: Some(TXOSpender { txid: txo.spending_txid, vin: txo.spending_vin }),
+        })
+        .collect(),
+      ScanEvent::Disconnected(rewound) => rewound.spent_txos.iter()
+        .filter(|(spent, _)| *spent == outpoint)
+        .filter_map(|(_, txo)| {
+          let height = txo.spent_height?;
+          Some(UTXOSpent {
+            outpoint,
+            height,
+            block_hash: rewound.blocks.iter().find(|block| block.height == height).map(|block| block.block_hash),
+            connected: false,
+            spender: txo.spender,
+          })
+        })
+        .collect(),
+    }))
+  }
+}
+
+// streams what `extract` picks from each event, ending with an error once the subscriber falls too far behind
+fn subscribe<T: Send + 'static>(
+  events: &ScanEvents,
+  extract: impl Fn(&ScanEvent) -> Vec<T> + Send + 'static,
+) -> EventStream<T> {
+  let mut receiver = events.subscribe();
+  Box::pin(async_stream::stream! {
+    loop {
+      match receiver.recv().await {
+        Ok(event) => {
+          for item in extract(&event) {
+            yield Ok(item);
+          }
+        }
+        Err(RecvError::Lagged(missed)) => {
+          yield Err(FieldError::from(format!("subscription fell behind by {} events", missed)));
+          break;
+        }
+        Err(RecvError::Closed) => break,
+      }
+    }
+  })
+}
+
+fn script_activities(event: &ScanEvent, script_hash: &ScriptHash) -> Vec<ScriptActivity> {
+  let mut generated_txos = Vec::new();
+  let mut spent_txos = Vec::new();
+  let (block_hashes, connected) = match event {
+    ScanEvent::Connected(connected) => {
+      for (outpoint, txo) in connected.generated_txos.iter().filter(|(_, txo)| txo.locker_script_hash == *script_hash) {
+        generated_txos.push((txo.generated_height, ActivityTXO { outpoint: *outpoint, value: txo.value, spender: None }));
+      }
+      for (outpoint, txo, _, value) in connected.spent_txos.iter().filter(|(_, _, locker_script_hash, _)| locker_script_hash == script_hash) {
+        spent_txos.push((txo.spent_height, ActivityTXO {
+          outpoint: *outpoint,
+          value: *value,
+          spender: Some(TXOSpender { txid: txo.spending_txid, vin: txo.spending_vin }),
+        }));
+      }
+      (connected.blocks.iter().copied().collect::<HashMap<_, _>>(), true)
+    }
+    ScanEvent::Disconnected(rewound) => {
+      for (outpoint, txo) in rewound.generated_txos.iter().filter(|(_, txo)| txo.locker_script_hash == *script_hash) {
+        generated_txos.push((txo.generated_height, ActivityTXO { outpoint: *outpoint, value: txo.value, spender: None }));
+      }
+      for (outpoint, txo) in rewound.spent_txos.iter().filter(|(_, txo)| txo.locker_script_hash == *script_hash) {
+        let Some(spent_height) = txo.spent_height else {
+          continue;
+        };
+        spent_txos.push((spent_height, ActivityTXO { outpoint: *outpoint, value: txo.value, spender: txo.spender }));
+      }
+      (rewound.blocks.iter().map(|block| (block.height, block.block_hash)).collect(), false)
+    }
+  };
+
+  let mut activities = BTreeMap::<BlockHeight, ScriptActivity>::new();
+  let new_activity = |height: BlockHeight| ScriptActivity {
+    height,
+    block_hash: block_hashes.get(&height).copied(),
+    connected,
+    generated_txos: Vec::new(),
+    spent_txos: Vec::new(),
+  };
+  for (height, txo) in generated_txos {
+    activities.entry(height).or_insert_with(|| new_activity(height)).generated_txos.push(txo);
+  }
+  for (height, txo) in spent_txos {
+    activities.entry(height).or_insert_with(|| new_activity(height)).spent_txos.push(txo);
+  }
+
+  let activities = activities.into_values();
+  // in the order the blocks were connected or disconnected
+  if connected {
+    activities.collect()
+  } else {
+    activities.rev().collect()
+  }
+}
+
+struct BlockEvent {
+  height: BlockHeight,
+  block_hash: BlockHash,
+  connected: bool,
+}
+
+#[graphql_object(rename_all = "none")]
+impl BlockEvent {
+  pub fn height(&self) -> String {
+    self.height.to_string()
+  }
+
+  pub fn block_hash(&self) -> String {
+    self.block_hash.to_string()
+  }
+
+  // false once the block is rewound for being on a stale chain
+  pub fn connected(&self) -> bool {
+    self.connected
+  }
+}
+
+// TXOs of a script generated and spent in a block
+struct ScriptActivity {
+  height: BlockHeight,
+  block_hash: Option<BlockHash>,
+  connected: bool,
+  generated_txos: Vec<ActivityTXO>,
+  spent_txos: Vec<ActivityTXO>,
+}
+
+#[graphql_object(rename_all = "none")]
+impl ScriptActivity {
+  pub fn height(&self) -> String {
+    self.height.to_string()
+  }
+
+  pub fn block_hash(&self) -> Option<String> {
+    self.block_hash.map(|block_hash| block_hash.to_string())
+  }
+
+  // false when the block is rewound, undoing the activity
+  pub fn connected(&self) -> bool {
+    self.connected
+  }
+
+  pub fn delta(&self) -> String {
+    let generated = self.generated_txos.iter().map(|txo| txo.value).sum::<Amount>();
+    let spent = self.spent_txos.iter().map(|txo| txo.value).sum::<Amount>();
+    (SignedAmount::from_sat(generated.to_sat() as i64) - SignedAmount::from_sat(spent.to_sat() as i64)).to_sat().to_string()
+  }
+
+  pub fn generated_txos(&self) -> &[ActivityTXO] {
+    &self.generated_txos
+  }
+
+  pub fn spent_txos(&self) -> &[ActivityTXO] {
+    &self.spent_txos
+  }
+}
+
+struct ActivityTXO {
+  outpoint: OutPoint,
+  value: Amount,
+  spender: Option<TXOSpender>,
+}
+
+#[graphql_object(rename_all = "none")]
+impl ActivityTXO {
+  pub fn txid(&self) -> String {
+    self.outpoint.txid.to_string()
+  }
+
+  pub fn vout(&self) -> i32 {
+    self.outpoint.vout as i32
+  }
+
+  pub fn value(&self) -> String {
+    self.value.to_sat().to_string()
+  }
+
+  pub fn spent_by(&self) -> Option<SpentBy> {
+    self.spender.map(|spender| SpentBy { spender })
+  }
+}
+
+struct UTXOSpent {
+  outpoint: OutPoint,
+  height: BlockHeight,
+  block_hash: Option<BlockHash>,
+  connected: bool,
+  spender: Option<TXOSpender>,
+}
+
+#[graphql_object(rename_all = "none")]
+impl UTXOSpent {
+  pub fn txid(&self) -> String {
+    self.outpoint.txid.to_string()
+  }
+
+  pub fn vout(&self) -> i32 {
+    self.outpoint.vout as i32
+  }
+
+  pub fn spent_height(&self) -> String {
+    self.height.to_string()
+  }
+
+  pub fn block_hash(&self) -> Option<String> {
+    self.block_hash.map(|block_hash| block_hash.to_string())
+  }
+
+  // false when the spending block is rewound, leaving the TXO unspent again
+  pub fn connected(&self) -> bool {
+    self.connected
+  }
+
+  // missing for TXOs spent before spenders were recorded
+  pub fn spent_by(&self) -> Option<SpentBy> {
+    self.spender.map(|spender| SpentBy { spender })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use bitcoin::{BlockHash, OutPoint, ScriptHash, Txid};
+  use futures::StreamExt as _;
+
+  use super::{script_activities, subscribe, ScriptActivity};
+  use crate::{scanner::events::{self, ScanEvent}, store::BlockHeight, test_util::{coinbase, other_script, outpoint, script, tx, TestStore}};
+
+  // height, block hash, connected, delta, generated TXOs and spent ones with their spending txid
+  type Summary = (BlockHeight, Option<BlockHash>, bool, String, Vec<(OutPoint, u64)>, Vec<(OutPoint, u64, Option<Txid>)>);
+
+  fn summarize(activity: &ScriptActivity) -> Summary {
+    (
+      activity.height,
+      activity.block_hash,
+      activity.connected,
+      activity.delta(),
+      activity.generated_txos.iter().map(|txo| (txo.outpoint, txo.value.to_sat())).collect(),
+      activity.spent_txos.iter().map(|txo| (txo.outpoint, txo.value.to_sat(), txo.spender.map(|spender| spender.txid))).collect(),
+    )
+  }
+
+  fn activities(event: &ScanEvent, script_hash: &ScriptHash) -> Vec<Summary> {
+    script_activities(event, script_hash).iter().map(summarize).collect()
+  }
+
+  #[test]
+  fn script_activities_are_undone_by_a_rewind() {
+    let test_store = TestStore::open("script-activities");
+    let script_hash = script().script_hash();
+
+    let coinbase0 = coinbase(0, &[(script(), 50)]);
+    let connected0 = test_store.connect_block(vec![coinbase0.clone()]);
+    let block_hash0 = connected0.blocks[0].1;
+    assert_eq!(activities(&ScanEvent::Connected(connected0), &script_hash), vec![
+      (0, Some(block_hash0), true, "50".to_string(), vec![(outpoint(&coinbase0, 0), 50)], vec![]),
+    ]);
+
+    let spending = tx(&[outpoint(&coinbase0, 0)], &[(other_script(), 20), (script(), 30)]);
+    let connected1 = test_store.connect_block(vec![coinbase(1, &[(other_script(), 50)]), spending.clone()]);
+    let block_hash1 = connected1.blocks[0].1;
+    let activity1 = (
+      1,
+      Some(block_hash1),
+      true,
+      "-20".to_string(),
+      vec![(outpoint(&spending, 1), 30)],
+      vec![(outpoint(&coinbase0, 0), 50, Some(spending.compute_txid()))],
+    );
+    assert_eq!(activities(&ScanEvent::Connected(connected1), &script_hash), vec![activity1.clone()]);
+    // blocks leaving the script untouched bring no activity
+    let connected2 = test_store.connect_block(vec![coinbase(2, &[(other_script(), 50)])]);
+    assert_eq!(activities(&ScanEvent::Connected(connected2), &script_hash), vec![]);
+
+    // the rewound activity is reported as it was connected, latest block first
+    let rewound = test_store.store().rewind_to(0).unwrap();
+    let disconnected1 = (activity1.0, activity1.1, false, activity1.3, activity1.4, activity1.5);
+    assert_eq!(activities(&ScanEvent::Disconnected(rewound), &script_hash), vec![disconnected1]);
+  }
+
+  #[tokio::test]
+  async fn subscribe_streams_extracted_items() {
+    let test_store = TestStore::open("subscribe");
+    let script_hash = script().script_hash();
+    let events = events::channel();
+    let stream = subscribe(&events, move |event| script_activities(event, &script_hash));
+
+    let connected0 = test_store.connect_block(vec![coinbase(0, &[(other_script(), 50)])]);
+    events::publish(&events, || ScanEvent::Connected(connected0));
+    let connected1 = test_store.connect_block(vec![coinbase(1, &[(script(), 25), (script(), 25)])]);
+    events::publish(&events, || ScanEvent::Connected(connected1));
+    let rewound = test_store.store().rewind_to(0).unwrap();
+    events::publish(&events, || ScanEvent::Disconnected(rewound));
+    drop(events);
+
+    // the first block has no activity of the script, the stream ends with the channel
+    let activities = stream.map(|activity| summarize(&activity.unwrap())).collect::<Vec<_>>().await;
+    assert_eq!(activities.iter().map(|(height, _, connected, delta, generated_txos, _)| (*height, *connected, delta.as_str(), generated_txos.len())).collect::<Vec<_>>(), vec![
+      (1, true, "50", 2),
+      (1, false, "50", 2),
+    ]);
+  }
+}
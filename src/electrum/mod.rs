@@ -0,0 +1,449 @@
+use std::{collections::HashMap, convert::Infallible, net::SocketAddr, sync::Arc, time::Duration};
+
+use bitcoin::{consensus, hashes::{sha256, Hash as _}, block::Header, BlockHash, ScriptHash, Txid};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use tokio::{io::{AsyncBufReadExt as _, AsyncReadExt as _, AsyncWriteExt as _, BufReader}, net::{tcp::OwnedWriteHalf, TcpListener, TcpStream}, select, task::block_in_place, time::interval};
+
+use crate::{fetch::HeaderFetcher, mempool::{Mempool, ScriptOverlay}, script_util::parse_electrum_script_hash, store::{balance::BalanceStoreRead as _, block::BlockStoreRead as _, history::HistoryStoreRead as _, script::ScriptStoreRead as _, BlockHeight, Seek, Store}};
+
+// See: https://electrum-protocol.readthedocs.io/en/latest/protocol-methods.html
+const PROTOCOL_VERSION: &str = "1.4";
+
+// connections sending longer request lines are closed
+const MAX_LINE_LENGTH: usize = 1024 * 1024;
+
+const ERROR_BAD_REQUEST: i64 = 1;
+const ERROR_PARSE: i64 = -32700;
+const ERROR_METHOD_NOT_FOUND: i64 = -32601;
+
+pub async fn serve_electrum<Fetcher>(
+  store: Arc<Store>,
+  mempool: Arc<Mempool>,
+  fetcher: Fetcher,
+  listen: SocketAddr,
+  poll_interval: Duration,
+) -> anyhow::Result<Infallible>
+where
+  Fetcher: HeaderFetcher + Send + Sync + 'static,
+{
+  let listener = TcpListener::bind(listen).await?;
+  println!("Electrum server listening on {}", listen);
+
+  let server = Arc::new(Server {
+    store,
+    mempool,
+    fetcher,
+    poll_interval,
+  });
+  loop {
+    let (stream, peer) = listener.accept().await?;
+    let server = server.clone();
+    tokio::spawn(async move {
+      if let Err(e) = server.handle_connection(stream).await {
+        tracing::debug!("electrum connection from {} failed: {:#}", peer, e);
+      }
+    });
+  }
+}
+
+struct Server<Fetcher> {
+  store: Arc<Store>,
+  mempool: Arc<Mempool>,
+  fetcher: Fetcher,
+  poll_interval: Duration,
+}
+
+#[derive(Default)]
+struct Session {
+  // tip the statuses of subscribed scripts were computed at
+  tip: Option<BlockHash>,
+  // tip last sent to the headers subscription, if any
+  headers_tip: Option<Option<BlockHash>>,
+  scripts: HashMap<String, ScriptSubscription>,
+}
+
+struct ScriptSubscription {
+  script_sha256: sha256::Hash,
+  script_hash: Option<ScriptHash>,
+  overlay: Option<ScriptOverlay>,
+  status: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Request {
+  #[serde(default)]
+  id: Value,
+  method: String,
+  #[serde(default)]
+  params: Vec<Value>,
+}
+
+struct RpcError {
+  code: i64,
+  message: String,
+}
+
+impl From<anyhow::Error> for RpcError {
+  fn from(e: anyhow::Error) -> Self {
+    Self {
+      code: ERROR_BAD_REQUEST,
+      message: format!("{:#}", e),
+    }
+  }
+}
+
+impl<Fetcher: HeaderFetcher> Server<Fetcher> {
+  async fn handle_connection(&self, stream: TcpStream) -> anyhow::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader).take(0);
+    let mut line = Vec::new();
+    let mut session = Session::default();
+    let mut ticker = interval(self.poll_interval);
+
+    loop {
+      // a read interrupted by a tick leaves what it read in `line`, the next one goes on from there
+      reader.set_limit((MAX_LINE_LENGTH + 1 - line.len()) as u64);
+      select! {
+        read = reader.read_until(b'\n', &mut line) => {
+          if read? == 0 && line.is_empty() {
+            return Ok(());
+          }
+          // the newline ending a line does not count, reads are allowed one more byte for it
+          if line.strip_suffix(b"\n").unwrap_or(&line[..]).len() > MAX_LINE_LENGTH {
+            anyhow::bail!("request line exceeds {} bytes", MAX_LINE_LENGTH);
+          }
+          let request = std::str::from_utf8(&line)?.trim();
+          if !request.is_empty() {
+            let response = self.handle_line(&mut session, request).await;
+            send(&mut writer, &response).await?;
+          }
+          line.clear();
+        }
+        _ = ticker.tick() => {
+          self.notify(&mut session, &mut writer).await?;
+        }
+      }
+    }
+  }
+
+  async fn handle_line(&self, session: &mut Session, line: &str) -> Value {
+    let message = match serde_json::from_str::<Value>(line) {
+      Ok(message) => message,
+      Err(e) => return error_response(Value::Null, RpcError { code: ERROR_PARSE, message: e.to_string() }),
+    };
+    match message {
+      Value::Array(requests) => {
+        let mut responses = Vec::with_capacity(requests.len());
+        for request in requests {
+          responses.push(self.handle_request(session, request).await);
+        }
+        Value::Array(responses)
+      }
+      request => self.handle_request(session, request).await,
+    }
+  }
+
+  async fn handle_request(&self, session: &mut Session, request: Value) -> Value {
+    let request = match serde_json::from_value::<Request>(request) {
+      Ok(request) => request,
+      Err(e) => return error_response(Value::Null, RpcError { code: ERROR_BAD_REQUEST, message: e.to_string() }),
+    };
+    match self.call(session, &request.method, &request.params).await {
+      Ok(result) => json!({ "jsonrpc": "2.0", "id": request.id, "result": result }),
+      Err(e) => error_response(request.id, e),
+    }
+  }
+
+  async fn call(&self, session: &mut Session, method: &str, params: &[Value]) -> Result<Value, RpcError> {
+    match method {
+      "server.version" => Ok(json!([concat!("scanner ", env!("CARGO_PKG_VERSION")), PROTOCOL_VERSION])),
+      "server.ping" => Ok(Value::Null),
+      "blockchain.headers.subscribe" => {
+        let tip = block_in_place(|| self.store.get_tip_block())?;
+        session.headers_tip = Some(tip.map(|(_, block_hash)| block_hash));
+        let Some((height, block_hash)) = tip else {
+          return Err(anyhow::anyhow!("no blocks are scanned yet").into());
+        };
+        Ok(self.header_notification(height, &block_hash).await?)
+      }
+      "blockchain.block.header" => {
+        let height = param::<BlockHeight>(params, 0)?;
+        if optional_param::<BlockHeight>(params, 1)?.unwrap_or(0) != 0 {
+          return Err(anyhow::anyhow!("checkpoint proofs are not supported").into());
+        }
+        let Some(block_hash) = block_in_place(|| self.store.get_block_hash(height))? else {
+          return Err(anyhow::anyhow!("no block at height {}", height).into());
+        };
+        let header = self.get_header(&block_hash).await?;
+        Ok(json!(hex::encode(consensus::serialize(&header))))
+      }
+      "blockchain.scripthash.get_balance" => {
+        let script_hash = self.resolve_script_hash(&parse_electrum_script_hash(&param::<String>(params, 0)?)?)?;
+        let Some(script_hash) = script_hash else {
+          return Ok(json!({ "confirmed": 0, "unconfirmed": 0 }));
+        };
+        let confirmed = block_in_place(|| self.store.get_balance_at(&script_hash, BlockHeight::MAX))?;
+        let unconfirmed = self.get_overlay(Some(script_hash))?.map_or(0, |overlay| overlay.balance_delta());
+        Ok(json!({ "confirmed": confirmed.to_sat(), "unconfirmed": unconfirmed }))
+      }
+      "blockchain.scripthash.get_history" => {
+        let script_hash = self.resolve_script_hash(&parse_electrum_script_hash(&param::<String>(params, 0)?)?)?;
+        let history = self.get_history(script_hash)?;
+        Ok(history.into_iter().map(|(height, txid)| json!({ "tx_hash": txid.to_string(), "height": height })).collect())
+      }
+      "blockchain.scripthash.listunspent" => {
+        let script_hash = self.resolve_script_hash(&parse_electrum_script_hash(&param::<String>(params, 0)?)?)?;
+        let Some(script_hash) = script_hash else {
+          return Ok(json!([]));
+        };
+        Ok(self.list_unspent(&script_hash)?)
+      }
+      "blockchain.scripthash.subscribe" => {
+        let electrum_script_hash = param::<String>(params, 0)?;
+        let script_sha256 = parse_electrum_script_hash(&electrum_script_hash)?;
+        let script_hash = self.resolve_script_hash(&script_sha256)?;
+        let overlay = self.get_overlay(script_hash)?;
+        let status = self.get_status(script_hash)?;
+        session.scripts.insert(electrum_script_hash, ScriptSubscription {
+          script_sha256,
+          script_hash,
+          overlay,
+          status: status.clone(),
+        });
+        Ok(json!(status))
+      }
+      "blockchain.scripthash.unsubscribe" => {
+        let electrum_script_hash = param::<String>(params, 0)?;
+        Ok(json!(session.scripts.remove(&electrum_script_hash).is_some()))
+      }
+      method => Err(RpcError {
+        code: ERROR_METHOD_NOT_FOUND,
+        message: format!("unknown method {}", method),
+      }),
+    }
+  }
+
+  // pushes the new tip and the changed script statuses to the session's subscriptions
+  async fn notify(&self, session: &mut Session, writer: &mut OwnedWriteHalf) -> anyhow::Result<()> {
+    let tip = block_in_place(|| self.store.get_tip_block())?;
+    let tip_hash = tip.map(|(_, block_hash)| block_hash);
+
+    if let Some(headers_tip) = session.headers_tip {
+      if let Some((height, block_hash)) = tip.filter(|_| headers_tip != tip_hash) {
+        let header = self.header_notification(height, &block_hash).await?;
+        send(writer, &json!({ "jsonrpc": "2.0", "method": "blockchain.headers.subscribe", "params": [header] })).await?;
+        session.headers_tip = Some(tip_hash);
+      }
+    }
+
+    let tip_changed = session.tip != tip_hash;
+    session.tip = tip_hash;
+    for (electrum_script_hash, subscription) in session.scripts.iter_mut() {
+      let script_hash = self.resolve_script_hash(&subscription.script_sha256)?;
+      let overlay = self.get_overlay(script_hash)?;
+      if !tip_changed && script_hash == subscription.script_hash && overlay == subscription.overlay {
+        continue;
+      }
+      subscription.script_hash = script_hash;
+      subscription.overlay = overlay;
+
+      let status = self.get_status(script_hash)?;
+      if status == subscription.status {
+        continue;
+      }
+      subscription.status = status.clone();
+      send(writer, &json!({ "jsonrpc": "2.0", "method": "blockchain.scripthash.subscribe", "params": [electrum_script_hash, status] })).await?;
+    }
+
+    Ok(())
+  }
+
+  // headers are not stored, they are fetched from the node by the stored block hash
+  async fn get_header(&self, block_hash: &BlockHash) -> anyhow::Result<Header> {
+    let Some(header) = self.fetcher.fetch_headers(block_hash, 1).await?.next().transpose()? else {
+      anyhow::bail!("node returned no header for block {}", block_hash);
+    };
+    if header.block_hash() != *block_hash {
+      anyhow::bail!("node returned header of block {} for block {}", header.block_hash(), block_hash);
+    }
+    Ok(header)
+  }
+
+  async fn header_notification(&self, height: BlockHeight, block_hash: &BlockHash) -> anyhow::Result<Value> {
+    let header = self.get_header(block_hash).await?;
+    Ok(json!({ "height": height, "hex": hex::encode(consensus::serialize(&header)) }))
+  }
+}
+
+// store and mempool queries, only headers need the node
+impl<Fetcher> Server<Fetcher> {
+  // scripts are known once they locked a TXO, confirmed or not
+  fn resolve_script_hash(&self, script_sha256: &sha256::Hash) -> anyhow::Result<Option<ScriptHash>> {
+    if let Some(script) = block_in_place(|| self.store.get_script_by_sha256(script_sha256))? {
+      return Ok(Some(script.script_hash()));
+    }
+    Ok(self.mempool.get_script_hash_by_sha256(script_sha256))
+  }
+
+  fn get_overlay(&self, script_hash: Option<ScriptHash>) -> anyhow::Result<Option<ScriptOverlay>> {
+    let Some(script_hash) = script_hash else {
+      return Ok(None);
+    };
+    block_in_place(|| self.mempool.get_script_overlay(&self.store, &script_hash))
+  }
+
+  // confirmed transactions in block order, followed by unconfirmed ones at height -1 when they spend unconfirmed
+  // outputs, 0 otherwise
+  fn get_history(&self, script_hash: Option<ScriptHash>) -> anyhow::Result<Vec<(i64, Txid)>> {
+    let Some(script_hash) = script_hash else {
+      return Ok(Vec::new());
+    };
+    let mut history = block_in_place(|| {
+      self.store.get_locker_script_transactions(&script_hash, Seek::First)?
+        .map(|entry| entry.map(|(location, txid, _)| (location.height as i64, txid)))
+        .collect::<anyhow::Result<Vec<_>>>()
+    })?;
+    if let Some(overlay) = self.get_overlay(Some(script_hash))? {
+      history.extend(overlay.txids.iter().map(|txid| {
+        (if overlay.txids_with_unconfirmed_inputs.contains(txid) { -1 } else { 0 }, *txid)
+      }));
+    }
+    Ok(history)
+  }
+
+  fn get_status(&self, script_hash: Option<ScriptHash>) -> anyhow::Result<Option<String>> {
+    let history = self.get_history(script_hash)?;
+    if history.is_empty() {
+      return Ok(None);
+    }
+    let status = history.iter().map(|(height, txid)| format!("{}:{}:", txid, height)).collect::<String>();
+    Ok(Some(hex::encode(sha256::Hash::hash(status.as_bytes()).to_byte_array())))
+  }
+
+  fn list_unspent(&self, script_hash: &ScriptHash) -> anyhow::Result<Value> {
+    let utxos = block_in_place(|| self.mempool.get_script_utxos(&self.store, script_hash))?;
+    Ok(
+      utxos.into_iter()
+        .map(|(outpoint, value, height)| json!({
+          "tx_hash": outpoint.txid.to_string(),
+          "tx_pos": outpoint.vout,
+          "height": height.unwrap_or(0),
+          "value": value.to_sat(),
+        }))
+        .collect()
+    )
+  }
+}
+
+fn param<T: DeserializeOwned>(params: &[Value], index: usize) -> anyhow::Result<T> {
+  let Some(param) = optional_param(params, index)? else {
+    anyhow::bail!("missing parameter {}", index);
+  };
+  Ok(param)
+}
+
+fn optional_param<T: DeserializeOwned>(params: &[Value], index: usize) -> anyhow::Result<Option<T>> {
+  let Some(param) = params.get(index) else {
+    return Ok(None);
+  };
+  Ok(Some(serde_json::from_value(param.clone())?))
+}
+
+fn error_response(id: Value, e: RpcError) -> Value {
+  json!({ "jsonrpc": "2.0", "id": id, "error": { "code": e.code, "message": e.message } })
+}
+
+async fn send(writer: &mut OwnedWriteHalf, message: &Value) -> anyhow::Result<()> {
+  let mut line = serde_json::to_vec(message)?;
+  line.push(b'\n');
+  writer.write_all(&line).await?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{collections::HashMap, sync::Arc, time::Duration};
+
+  use bitcoin::{hashes::{sha256, Hash as _}, Txid};
+  use serde_json::json;
+
+  use super::Server;
+  use crate::{mempool::Mempool, test_util::{coinbase, other_script, outpoint, script, tx, TestStore}};
+
+  fn server(test_store: &TestStore) -> Server<()> {
+    Server {
+      store: test_store.store().clone(),
+      mempool: Arc::new(Mempool::default()),
+      fetcher: (),
+      poll_interval: Duration::from_secs(1),
+    }
+  }
+
+  fn status(history: &[(i64, Txid)]) -> String {
+    let status = history.iter().map(|(height, txid)| format!("{}:{}:", txid, height)).collect::<String>();
+    hex::encode(sha256::Hash::hash(status.as_bytes()).to_byte_array())
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn status_and_unspent_follow_block_order_then_mempool() {
+    let test_store = TestStore::open("electrum-status");
+    let coinbase0 = coinbase(0, &[(script(), 50)]);
+    test_store.connect_block(vec![coinbase0.clone()]);
+    let coinbase1 = coinbase(1, &[(other_script(), 50)]);
+    let funding = tx(&[outpoint(&coinbase1, 0)], &[(script(), 20), (other_script(), 30)]);
+    test_store.connect_block(vec![coinbase1.clone(), funding.clone()]);
+    let coinbase2 = coinbase(2, &[(other_script(), 50)]);
+    let other_funding = tx(&[outpoint(&funding, 1)], &[(script(), 30)]);
+    test_store.connect_block(vec![coinbase2.clone(), other_funding.clone()]);
+
+    let server = server(&test_store);
+    let script_hash = script().script_hash();
+    let confirmed = vec![
+      (0, coinbase0.compute_txid()),
+      (1, funding.compute_txid()),
+      (2, other_funding.compute_txid()),
+    ];
+    assert_eq!(server.get_history(Some(script_hash)).unwrap(), confirmed);
+    assert_eq!(server.get_status(Some(script_hash)).unwrap(), Some(status(&confirmed)));
+    assert_eq!(server.get_status(Some(other_script().script_hash())).unwrap(), Some(status(&[
+      (1, coinbase1.compute_txid()),
+      (1, funding.compute_txid()),
+      (2, coinbase2.compute_txid()),
+      (2, other_funding.compute_txid()),
+    ])));
+    assert_eq!(server.list_unspent(&script_hash).unwrap(), json!([
+      { "tx_hash": coinbase0.compute_txid().to_string(), "tx_pos": 0, "height": 0, "value": 50 },
+      { "tx_hash": funding.compute_txid().to_string(), "tx_pos": 0, "height": 1, "value": 20 },
+      { "tx_hash": other_funding.compute_txid().to_string(), "tx_pos": 0, "height": 2, "value": 30 },
+    ]));
+
+    // the child spends an output of its unconfirmed parent
+    let parent = tx(&[outpoint(&coinbase0, 0)], &[(script(), 40), (other_script(), 10)]);
+    let child = tx(&[outpoint(&parent, 0)], &[(script(), 40)]);
+    let mut txs = HashMap::from([(parent.compute_txid(), parent.clone()), (child.compute_txid(), child.clone())]);
+    server.mempool.update(test_store.store(), &mut txs).unwrap();
+
+    let mut unconfirmed = vec![(0, parent.compute_txid()), (-1, child.compute_txid())];
+    unconfirmed.sort_by_key(|(_, txid)| *txid);
+    let history = confirmed.into_iter().chain(unconfirmed).collect::<Vec<_>>();
+    assert_eq!(server.get_history(Some(script_hash)).unwrap(), history);
+    assert_eq!(server.get_status(Some(script_hash)).unwrap(), Some(status(&history)));
+    assert_eq!(server.list_unspent(&script_hash).unwrap(), json!([
+      { "tx_hash": funding.compute_txid().to_string(), "tx_pos": 0, "height": 1, "value": 20 },
+      { "tx_hash": other_funding.compute_txid().to_string(), "tx_pos": 0, "height": 2, "value": 30 },
+      { "tx_hash": child.compute_txid().to_string(), "tx_pos": 0, "height": 0, "value": 40 },
+    ]));
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn unknown_script_has_no_status() {
+    let test_store = TestStore::open("electrum-unknown");
+    test_store.connect_block(vec![coinbase(0, &[(other_script(), 50)])]);
+
+    let server = server(&test_store);
+    assert_eq!(server.get_status(Some(script().script_hash())).unwrap(), None);
+    assert_eq!(server.get_status(None).unwrap(), None);
+    assert_eq!(server.list_unspent(&script().script_hash()).unwrap(), json!([]));
+  }
+}
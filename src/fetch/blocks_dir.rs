@@ -1,12 +1,38 @@
-use std::path::{PathBuf};
+use std::{collections::HashMap, fs::File, io::{self, Read, Seek, SeekFrom}, path::PathBuf, sync::Mutex};
 
 use async_trait::async_trait;
-use bitcoin::{BlockHash};
+use bitcoin::{block::Header, consensus, p2p::Magic, BlockHash, Network};
+use bytes::Bytes;
+use tokio::task::block_in_place;
 
-use crate::fetch::{BlockFetcher};
+use crate::fetch::{rest_api::BlockBytes, BlockFetcher};
+
+// See: https://github.com/bitcoin/bitcoin/blob/master/src/node/blockstorage.h
+// Each record in a blk*.dat file is laid out as [magic: 4][size: u32 LE][block: size].
+// Since v28 every byte of the file is XORed with the 8-byte key in xor.dat, keyed by file offset.
+
+const RECORD_HEADER_SIZE: u64 = 8;
 
 pub struct BlocksDirReader {
   blocks_dir: PathBuf,
+  xor_key: [u8; 8],
+  index: Mutex<BlockIndex>,
+}
+
+#[derive(Clone, Copy)]
+struct BlockLocation {
+  file: u32,
+  offset: u64,
+  size: u32,
+}
+
+#[derive(Default)]
+struct BlockIndex {
+  magic: Option<Magic>,
+  locations: HashMap<BlockHash, BlockLocation>,
+  // position up to which the files have been indexed
+  next_file: u32,
+  next_offset: u64,
 }
 
 impl BlocksDirReader {
@@ -14,20 +40,279 @@ impl BlocksDirReader {
     blocks_dir: String,
   ) -> Result<Self, anyhow::Error> {
     let blocks_dir = PathBuf::try_from(&blocks_dir)?;
-    Ok(Self { blocks_dir })
+    if !blocks_dir.is_dir() {
+      anyhow::bail!("blocks dir {} is not a directory", blocks_dir.display());
+    }
+
+    let xor_key = match std::fs::read(blocks_dir.join("xor.dat")) {
+      Ok(key) => key.as_slice().try_into().map_err(
+        |_| anyhow::anyhow!("xor.dat must be exactly 8 bytes, got {}", key.len())
+      )?,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => [0; 8],
+      Err(e) => return Err(e.into()),
+    };
+
+    Ok(Self {
+      blocks_dir,
+      xor_key,
+      index: Mutex::new(BlockIndex::default()),
+    })
+  }
+
+  fn file_path(&self, file: u32) -> PathBuf {
+    self.blocks_dir.join(format!("blk{:05}.dat", file))
+  }
+
+  fn deobfuscate(&self, offset: u64, data: &mut [u8]) {
+    for (i, byte) in data.iter_mut().enumerate() {
+      *byte ^= self.xor_key[(offset as usize + i) % self.xor_key.len()];
+    }
+  }
+
+  fn read_at(&self, reader: &mut (impl Read + Seek), offset: u64, data: &mut [u8]) -> io::Result<()> {
+    reader.seek(SeekFrom::Start(offset))?;
+    reader.read_exact(data)?;
+    self.deobfuscate(offset, data);
+    Ok(())
+  }
+
+  fn locate_block(&self, block_hash: &BlockHash) -> anyhow::Result<Option<BlockLocation>> {
+    let mut index = self.index.lock().unwrap();
+    if let Some(location) = index.locations.get(block_hash) {
+      return Ok(Some(*location));
+    }
+
+    // bitcoind keeps appending to the files, so pick up from where the last scan stopped
+    self.scan_files(&mut index)?;
+
+    Ok(index.locations.get(block_hash).copied())
+  }
+
+  #[tracing::instrument(name = "BlocksDirReader::scan_files", level = "trace", skip_all, fields(
+    from_file = index.next_file,
+    num_blocks = tracing::field::Empty,
+  ))]
+  fn scan_files(&self, index: &mut BlockIndex) -> anyhow::Result<()> {
+    // the last file is left as the position, so the next scan picks up what bitcoind appends to it
+    for file_number in self.list_files(index.next_file)? {
+      if file_number != index.next_file {
+        // a following file means bitcoind has moved on, the rest of the previous one is preallocated space.
+        // Pruned nodes delete files from the start, so a gap is skipped rather than taken as the end.
+        index.next_file = file_number;
+        index.next_offset = 0;
+      }
+      let file = match File::open(self.file_path(file_number)) {
+        Ok(file) => file,
+        // pruned since listed
+        Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+        Err(e) => return Err(e.into()),
+      };
+      self.scan_file(index, file)?;
+    }
+
+    tracing::Span::current().record("num_blocks", index.locations.len());
+
+    Ok(())
+  }
+
+  // numbers of the blk*.dat files present from `from_file` on, in ascending order
+  fn list_files(&self, from_file: u32) -> anyhow::Result<Vec<u32>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(&self.blocks_dir)? {
+      let file_name = entry?.file_name();
+      let Some(file_number) = file_name.to_str().and_then(parse_file_number) else {
+        continue;
+      };
+      if file_number >= from_file {
+        files.push(file_number);
+      }
+    }
+    files.sort();
+    Ok(files)
+  }
+
+  fn scan_file(&self, index: &mut BlockIndex, mut file: File) -> anyhow::Result<()> {
+    let file_size = file.metadata()?.len();
+
+    // record header followed by the block header, read in one go
+    let mut record = [0u8; RECORD_HEADER_SIZE as usize + Header::SIZE];
+    while index.next_offset + record.len() as u64 <= file_size {
+      let offset = index.next_offset;
+      self.read_at(&mut file, offset, &mut record)?;
+
+      let magic = Magic::from_bytes(record[..4].try_into().unwrap());
+      if magic.to_bytes() == [0; 4] {
+        // preallocated space not written to yet
+        break;
+      }
+      match index.magic {
+        Some(expected) if expected != magic => {
+          anyhow::bail!(
+            "unexpected network magic {} at offset {} of {}",
+            magic,
+            offset,
+            self.file_path(index.next_file).display(),
+          );
+        }
+        Some(_) => {}
+        None => {
+          tracing::info!("blocks dir network magic is {} ({:?})", magic, Network::from_magic(magic));
+          index.magic = Some(magic);
+        }
+      }
+
+      let size = u32::from_le_bytes(record[4..8].try_into().unwrap());
+      let block_offset = offset + RECORD_HEADER_SIZE;
+      if block_offset + size as u64 > file_size {
+        // block is still being flushed
+        break;
+      }
+
+      let header: Header = consensus::deserialize(&record[RECORD_HEADER_SIZE as usize..]).map_err(
+        |e| anyhow::anyhow!("Failed to deserialize block header at offset {}: {}", block_offset, e)
+      )?;
+
+      index.locations.insert(header.block_hash(), BlockLocation {
+        file: index.next_file,
+        offset: block_offset,
+        size,
+      });
+      index.next_offset = block_offset + size as u64;
+    }
+    Ok(())
+  }
+
+  fn read_block(&self, location: BlockLocation) -> anyhow::Result<Bytes> {
+    let mut file = File::open(self.file_path(location.file))?;
+    let mut data = vec![0u8; location.size as usize];
+    self.read_at(&mut file, location.offset, &mut data)?;
+    Ok(Bytes::from(data))
   }
 }
 
+fn parse_file_number(file_name: &str) -> Option<u32> {
+  file_name.strip_prefix("blk")?.strip_suffix(".dat")?.parse().ok()
+}
+
 #[async_trait]
 impl BlockFetcher for BlocksDirReader {
-  type FetchedBlock = super::rest_api::BlockBytes;
+  type FetchedBlock = BlockBytes;
 
   async fn fetch_block(
     &self,
     block_hash: &BlockHash,
-  ) -> anyhow::Result<super::rest_api::BlockBytes> {
-    anyhow::bail!(
-      "BlocksDirReader::fetch_block is not yet implemented"
-    )
+  ) -> anyhow::Result<BlockBytes> {
+    block_in_place(|| {
+      let Some(location) = self.locate_block(block_hash)? else {
+        anyhow::bail!("block {} not found in blocks dir", block_hash);
+      };
+      Ok(BlockBytes(self.read_block(location)?))
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{io::Write as _, path::PathBuf};
+
+  use bitcoin::{consensus, hashes::Hash as _, Block, BlockHash, Network};
+
+  use super::BlocksDirReader;
+  use crate::test_util::{block, coinbase, script};
+
+  const XOR_KEY: [u8; 8] = [0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88];
+
+  // blk*.dat files written like bitcoind does, obfuscated with `XOR_KEY`
+  struct TestBlocksDir {
+    path: PathBuf,
+  }
+
+  impl TestBlocksDir {
+    fn create(name: &str) -> Self {
+      let path = std::env::temp_dir().join(format!("blocks-dir-test-{}-{}", name, std::process::id()));
+      _ = std::fs::remove_dir_all(&path);
+      std::fs::create_dir_all(&path).unwrap();
+      std::fs::write(path.join("xor.dat"), XOR_KEY).unwrap();
+      Self { path }
+    }
+
+    // appends `data` to blk`file`.dat, obfuscated by file offset
+    fn append(&self, file: u32, data: &[u8]) {
+      let mut file = std::fs::OpenOptions::new().create(true).append(true).open(self.path.join(format!("blk{:05}.dat", file))).unwrap();
+      let offset = file.metadata().unwrap().len() as usize;
+      let data = data.iter().enumerate().map(|(i, byte)| byte ^ XOR_KEY[(offset + i) % XOR_KEY.len()]).collect::<Vec<_>>();
+      file.write_all(&data).unwrap();
+    }
+
+    fn reader(&self) -> BlocksDirReader {
+      BlocksDirReader::try_open(self.path.to_str().unwrap().to_string()).unwrap()
+    }
+  }
+
+  impl Drop for TestBlocksDir {
+    fn drop(&mut self) {
+      _ = std::fs::remove_dir_all(&self.path);
+    }
+  }
+
+  // blocks of different heights differ in size, with one more output each
+  fn test_block(height: u32) -> Block {
+    let outputs = vec![(script(), 50); height as usize + 1];
+    block(height, BlockHash::all_zeros(), vec![coinbase(height as i64, &outputs)])
+  }
+
+  fn record(block: &Block) -> Vec<u8> {
+    let data = consensus::serialize(block);
+    [Network::Regtest.magic().to_bytes().as_slice(), &(data.len() as u32).to_le_bytes(), &data].concat()
+  }
+
+  fn read(reader: &BlocksDirReader, block: &Block) -> Option<Block> {
+    let location = reader.locate_block(&block.block_hash()).unwrap()?;
+    Some(consensus::deserialize(&reader.read_block(location).unwrap()).unwrap())
+  }
+
+  #[test]
+  fn deobfuscates_by_file_offset() {
+    let blocks_dir = TestBlocksDir::create("xor");
+    // the second record starts at an offset not aligned with the key
+    let blocks = [test_block(1), test_block(2)];
+    blocks_dir.append(0, &[record(&blocks[0]), record(&blocks[1])].concat());
+    assert_ne!(record(&blocks[0]).len() % XOR_KEY.len(), 0);
+
+    let reader = blocks_dir.reader();
+    assert_eq!(read(&reader, &blocks[1]), Some(blocks[1].clone()));
+    assert_eq!(read(&reader, &blocks[0]), Some(blocks[0].clone()));
+  }
+
+  #[test]
+  fn skips_gaps_in_file_numbers() {
+    let blocks_dir = TestBlocksDir::create("gaps");
+    let blocks = [test_block(0), test_block(1), test_block(2)];
+    // blk00001.dat pruned
+    blocks_dir.append(0, &record(&blocks[0]));
+    blocks_dir.append(2, &record(&blocks[2]));
+
+    let reader = blocks_dir.reader();
+    assert_eq!(read(&reader, &blocks[2]), Some(blocks[2].clone()));
+    assert_eq!(read(&reader, &blocks[0]), Some(blocks[0].clone()));
+    assert_eq!(read(&reader, &blocks[1]), None);
+  }
+
+  #[test]
+  fn stops_at_unwritten_space_and_partial_blocks() {
+    let blocks_dir = TestBlocksDir::create("partial");
+    let blocks = [test_block(0), test_block(1), test_block(2)];
+    let partial = record(&blocks[1]);
+    let (flushed, unflushed) = partial.split_at(partial.len() / 2);
+    blocks_dir.append(0, &[record(&blocks[0]).as_slice(), flushed].concat());
+
+    let reader = blocks_dir.reader();
+    assert_eq!(read(&reader, &blocks[0]), Some(blocks[0].clone()));
+    assert_eq!(read(&reader, &blocks[1]), None);
+
+    // picked up once flushed, up to the preallocated space following it
+    blocks_dir.append(0, &[unflushed, &[0; 1024]].concat());
+    assert_eq!(read(&reader, &blocks[1]), Some(blocks[1].clone()));
+    assert_eq!(read(&reader, &blocks[2]), None);
   }
 }
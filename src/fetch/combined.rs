@@ -1,39 +1,81 @@
 use std::sync::Arc;
 
 use async_trait::async_trait;
+use tokio::task::block_in_place;
 
-use crate::fetch::{blocks_dir::BlocksDirReader, rest_api::{self, BitcoinRestClient}};
+use crate::fetch::{blocks_dir::BlocksDirReader, node::NodeClient, rest_api::BlockBytes, BlockFetcher as _, HashFetcher as _, HeaderFetcher as _, MempoolFetcher as _};
 
 pub struct CombinedFetcher {
-  pub rest_client: BitcoinRestClient,
+  pub node_client: NodeClient,
   pub blocks_dir: Option<BlocksDirReader>,
 }
 
 impl CombinedFetcher {
   pub fn new(
-    rest_client: BitcoinRestClient,
+    node_client: NodeClient,
     blocks_dir: Option<BlocksDirReader>,
   ) -> Self {
     Self {
-      rest_client,
+      node_client,
       blocks_dir,
     }
   }
 }
 
+pub struct CombinedBlock(pub bitcoin::Block);
+
+impl TryInto<bitcoin::Block> for CombinedBlock {
+  type Error = anyhow::Error;
+
+  fn try_into(self) -> Result<bitcoin::Block, Self::Error> {
+    Ok(self.0)
+  }
+}
+
+fn parse_block(
+  bytes: BlockBytes,
+  block_hash: &bitcoin::BlockHash,
+) -> anyhow::Result<bitcoin::Block> {
+  block_in_place(|| {
+    let block: bitcoin::Block = bytes.try_into()?;
+    if block.block_hash() != *block_hash {
+      anyhow::bail!("expected block {} but got {}", block_hash, block.block_hash());
+    }
+    Ok(block)
+  })
+}
+
 #[async_trait]
 impl crate::fetch::BlockFetcher for Arc<CombinedFetcher> {
-  type FetchedBlock = rest_api::BlockBytes;
+  type FetchedBlock = CombinedBlock;
 
+  #[tracing::instrument(name = "CombinedFetcher::fetch_block", level = "trace", skip_all, fields(
+    block_hash = %block_hash,
+    source = tracing::field::Empty,
+  ))]
   async fn fetch_block(
     &self,
     block_hash: &bitcoin::BlockHash,
-  ) -> anyhow::Result<rest_api::BlockBytes> {
+  ) -> anyhow::Result<CombinedBlock> {
+    // blocks not yet flushed to disk, pruned or corrupted on disk are served by the node instead
     if let Some(blocks_dir) = &self.blocks_dir {
-      return blocks_dir.fetch_block(block_hash).await;
+      match blocks_dir.fetch_block(block_hash).await.and_then(|bytes| parse_block(bytes, block_hash)) {
+        Ok(block) => {
+          tracing::Span::current().record("source", "blocks_dir");
+          return Ok(CombinedBlock(block));
+        }
+        Err(e) => {
+          tracing::debug!("falling back to the node for block {}: {}", block_hash, e);
+        }
+      }
     }
 
-    self.rest_client.fetch_block(block_hash).await
+    let bytes = self.node_client.fetch_block(block_hash).await?;
+    tracing::Span::current().record("source", match self.node_client {
+      NodeClient::Rest(_) => "rest",
+      NodeClient::Rpc(_) => "rpc",
+    });
+    Ok(CombinedBlock(parse_block(bytes, block_hash)?))
   }
 }
 
@@ -44,7 +86,7 @@ impl crate::fetch::HeaderFetcher for Arc<CombinedFetcher> {
     from_block_hash: &bitcoin::BlockHash,
     count: usize,
   ) -> anyhow::Result<Box<dyn Send + Iterator<Item = anyhow::Result<bitcoin::block::Header>>>> {
-    self.rest_client.fetch_headers(from_block_hash, count).await
+    self.node_client.fetch_headers(from_block_hash, count).await
   }
 }
 
@@ -53,7 +95,23 @@ impl crate::fetch::HashFetcher for Arc<CombinedFetcher> {
   async fn fetch_hash(
     &self,
     height: u32,
-  ) -> anyhow::Result<bitcoin::BlockHash> {
-    self.rest_client.fetch_hash(height).await
+  ) -> anyhow::Result<Option<bitcoin::BlockHash>> {
+    self.node_client.fetch_hash(height).await
+  }
+}
+
+#[async_trait]
+impl crate::fetch::MempoolFetcher for Arc<CombinedFetcher> {
+  async fn fetch_mempool_txids(
+    &self,
+  ) -> anyhow::Result<Vec<bitcoin::Txid>> {
+    self.node_client.fetch_mempool_txids().await
+  }
+
+  async fn fetch_mempool_transaction(
+    &self,
+    txid: &bitcoin::Txid,
+  ) -> anyhow::Result<Option<bitcoin::Transaction>> {
+    self.node_client.fetch_mempool_transaction(txid).await
   }
 }
@@ -1,9 +1,11 @@
 pub mod rest_api;
 pub mod blocks_dir;
 pub mod combined;
+pub mod rpc;
+pub mod node;
 
 use async_trait::async_trait;
-use bitcoin::{block::Header, Block, BlockHash};
+use bitcoin::{block::Header, Block, BlockHash, Transaction, Txid};
 
 #[async_trait]
 pub trait BlockFetcher {
@@ -29,5 +31,17 @@ pub trait HashFetcher {
   async fn fetch_hash(
     &self,
     height: u32,
-  ) -> anyhow::Result<BlockHash>;
+  ) -> anyhow::Result<Option<BlockHash>>;
+}
+
+#[async_trait]
+pub trait MempoolFetcher {
+  async fn fetch_mempool_txids(
+    &self,
+  ) -> anyhow::Result<Vec<Txid>>;
+
+  async fn fetch_mempool_transaction(
+    &self,
+    txid: &Txid,
+  ) -> anyhow::Result<Option<Transaction>>;
 }
@@ -0,0 +1,74 @@
+use async_trait::async_trait;
+use bitcoin::{BlockHash, Transaction, Txid};
+
+use crate::fetch::{rest_api::{BitcoinRestClient, BlockBytes}, rpc::BitcoinRpcClient, BlockFetcher, HashFetcher, HeaderFetcher, MempoolFetcher};
+
+#[derive(Clone)]
+pub enum NodeClient {
+  Rest(BitcoinRestClient),
+  Rpc(BitcoinRpcClient),
+}
+
+#[async_trait]
+impl BlockFetcher for NodeClient {
+  type FetchedBlock = BlockBytes;
+
+  async fn fetch_block(
+    &self,
+    block_hash: &BlockHash,
+  ) -> anyhow::Result<BlockBytes> {
+    match self {
+      NodeClient::Rest(client) => client.fetch_block(block_hash).await,
+      NodeClient::Rpc(client) => client.fetch_block(block_hash).await,
+    }
+  }
+}
+
+#[async_trait]
+impl HeaderFetcher for NodeClient {
+  async fn fetch_headers(
+    &self,
+    from_block_hash: &BlockHash,
+    count: usize,
+  ) -> anyhow::Result<Box<dyn Send + Iterator<Item = anyhow::Result<bitcoin::block::Header>>>> {
+    match self {
+      NodeClient::Rest(client) => client.fetch_headers(from_block_hash, count).await,
+      NodeClient::Rpc(client) => client.fetch_headers(from_block_hash, count).await,
+    }
+  }
+}
+
+#[async_trait]
+impl HashFetcher for NodeClient {
+  async fn fetch_hash(
+    &self,
+    height: u32,
+  ) -> anyhow::Result<Option<BlockHash>> {
+    match self {
+      NodeClient::Rest(client) => client.fetch_hash(height).await,
+      NodeClient::Rpc(client) => client.fetch_hash(height).await,
+    }
+  }
+}
+
+#[async_trait]
+impl MempoolFetcher for NodeClient {
+  async fn fetch_mempool_txids(
+    &self,
+  ) -> anyhow::Result<Vec<Txid>> {
+    match self {
+      NodeClient::Rest(client) => client.fetch_mempool_txids().await,
+      NodeClient::Rpc(client) => client.fetch_mempool_txids().await,
+    }
+  }
+
+  async fn fetch_mempool_transaction(
+    &self,
+    txid: &Txid,
+  ) -> anyhow::Result<Option<Transaction>> {
+    match self {
+      NodeClient::Rest(client) => client.fetch_mempool_transaction(txid).await,
+      NodeClient::Rpc(client) => client.fetch_mempool_transaction(txid).await,
+    }
+  }
+}
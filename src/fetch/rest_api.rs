@@ -1,10 +1,10 @@
 use std::iter;
 
 use async_trait::async_trait;
-use bitcoin::{consensus, BlockHash};
+use bitcoin::{consensus, BlockHash, Transaction, Txid};
 use bytes::Bytes;
 
-use crate::fetch::{BlockFetcher, HashFetcher, HeaderFetcher};
+use crate::fetch::{BlockFetcher, HashFetcher, HeaderFetcher, MempoolFetcher};
 
 #[derive(Clone)]
 pub struct BitcoinRestClient {
@@ -87,16 +87,55 @@ impl HeaderFetcher for BitcoinRestClient {
 
 #[async_trait]
 impl HashFetcher for BitcoinRestClient {
-  async fn fetch_hash(&self, height: u32) -> anyhow::Result<BlockHash> {
-    let binary = self.client.get(format!("{}/rest/blockhashbyheight/{}.bin", &self.url, height))
+  async fn fetch_hash(&self, height: u32) -> anyhow::Result<Option<BlockHash>> {
+    let response = self.client.get(format!("{}/rest/blockhashbyheight/{}.bin", &self.url, height))
       .send()
-      .await?
+      .await?;
+
+    // the node answers 404 for heights above its tip
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+      return Ok(None);
+    }
+
+    let binary = response
       .error_for_status()?
       .bytes()
       .await?;
 
-    Ok(consensus::deserialize(&binary).map_err(
+    Ok(Some(consensus::deserialize(&binary).map_err(
       |e| anyhow::anyhow!("Failed to deserialize block hash: {}", e)
-    )?)
+    )?))
+  }
+}
+
+#[async_trait]
+impl MempoolFetcher for BitcoinRestClient {
+  async fn fetch_mempool_txids(&self) -> anyhow::Result<Vec<Txid>> {
+    Ok(self.client.get(format!("{}/rest/mempool/contents.json?verbose=false", &self.url))
+      .send()
+      .await?
+      .error_for_status()?
+      .json()
+      .await?)
+  }
+
+  async fn fetch_mempool_transaction(&self, txid: &Txid) -> anyhow::Result<Option<Transaction>> {
+    let response = self.client.get(format!("{}/rest/tx/{}.bin", &self.url, txid))
+      .send()
+      .await?;
+
+    // evicted or confirmed since the txids were listed
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+      return Ok(None);
+    }
+
+    let binary = response
+      .error_for_status()?
+      .bytes()
+      .await?;
+
+    Ok(Some(consensus::deserialize(&binary).map_err(
+      |e| anyhow::anyhow!("Failed to deserialize transaction: {}", e)
+    )?))
   }
 }
@@ -0,0 +1,212 @@
+use std::{path::PathBuf, str::FromStr, sync::{Arc, RwLock}};
+
+use anyhow::Context as _;
+use async_trait::async_trait;
+use base64::Engine as _;
+use bitcoin::{consensus, BlockHash, Transaction, Txid};
+use bytes::Bytes;
+use jsonrpsee::{core::{client::ClientT as _, params::{ArrayParams, BatchRequestBuilder}, ClientError}, http_client::{transport, HeaderMap, HeaderValue, HttpClient}, rpc_params};
+use serde::Deserialize;
+
+use crate::fetch::{rest_api::BlockBytes, BlockFetcher, HashFetcher, HeaderFetcher, MempoolFetcher};
+
+// bitcoind answers getblockhash with this code for heights above its tip
+const RPC_INVALID_PARAMETER: i32 = -8;
+// bitcoind answers getrawtransaction with this code for unknown transactions
+const RPC_INVALID_ADDRESS_OR_KEY: i32 = -5;
+
+pub enum RpcAuth {
+  None,
+  CookieFile(PathBuf),
+  UserPassword {
+    user: String,
+    password: String,
+  },
+}
+
+#[derive(Clone)]
+pub struct BitcoinRpcClient {
+  url: String,
+  auth: Arc<RpcAuth>,
+  client: Arc<RwLock<HttpClient>>,
+}
+
+impl BitcoinRpcClient {
+  // See: https://github.com/bitcoin/bitcoin/blob/0eeae4d174a41c3fc2eae41e76b929fa3114d6f3/doc/JSON-RPC-interface.md
+
+  pub fn new(
+    url: String,
+    auth: RpcAuth,
+  ) -> anyhow::Result<Self> {
+    let client = build_client(&url, &auth)?;
+    Ok(Self {
+      url,
+      auth: Arc::new(auth),
+      client: Arc::new(RwLock::new(client)),
+    })
+  }
+
+  fn client(&self) -> HttpClient {
+    self.client.read().unwrap().clone()
+  }
+
+  // bitcoind rewrites its cookie file on every start, so credentials read before a node restart get rejected
+  fn reload_cookie(&self) -> bool {
+    let RpcAuth::CookieFile(path) = self.auth.as_ref() else {
+      return false;
+    };
+    match build_client(&self.url, &self.auth) {
+      Ok(client) => {
+        tracing::info!("Reloaded RPC cookie file {}", path.display());
+        *self.client.write().unwrap() = client;
+        true
+      }
+      Err(e) => {
+        tracing::warn!("Failed to reload RPC cookie file: {:#}", e);
+        false
+      }
+    }
+  }
+
+  // retried once with a reloaded cookie when rejected as unauthorized
+  async fn request<R: serde::de::DeserializeOwned>(
+    &self,
+    method: &str,
+    params: ArrayParams,
+  ) -> Result<R, ClientError> {
+    match self.client().request(method, params.clone()).await {
+      Err(e) if is_unauthorized(&e) && self.reload_cookie() => self.client().request(method, params).await,
+      res => res,
+    }
+  }
+
+  async fn batch<'a, R: serde::de::DeserializeOwned + std::fmt::Debug + 'a>(
+    &self,
+    batch: BatchRequestBuilder<'a>,
+  ) -> anyhow::Result<Vec<R>> {
+    let response = match self.client().batch_request::<R>(batch.clone()).await {
+      Err(e) if is_unauthorized(&e) && self.reload_cookie() => self.client().batch_request::<R>(batch).await,
+      res => res,
+    };
+    response?
+      .into_iter()
+      .map(|entry| entry.map_err(
+        |e| anyhow::anyhow!("RPC batch entry failed with code {}: {}", e.code(), e.message())
+      ))
+      .collect()
+  }
+}
+
+fn build_client(url: &str, auth: &RpcAuth) -> anyhow::Result<HttpClient> {
+  let credentials = match auth {
+    RpcAuth::None => None,
+    RpcAuth::CookieFile(path) => Some(
+      std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read RPC cookie file {}", path.display()))?
+        .trim()
+        .to_owned()
+    ),
+    RpcAuth::UserPassword { user, password } => Some(format!("{}:{}", user, password)),
+  };
+
+  let mut headers = HeaderMap::new();
+  if let Some(credentials) = credentials {
+    let encoded = base64::engine::general_purpose::STANDARD.encode(credentials);
+    headers.insert("authorization", HeaderValue::from_str(&format!("Basic {}", encoded))?);
+  }
+
+  Ok(
+    HttpClient::builder()
+      // hex encoded blocks are twice their serialized size
+      .max_response_size(32 * 1024 * 1024)
+      .set_headers(headers)
+      .build(url)?
+  )
+}
+
+fn is_unauthorized(e: &ClientError) -> bool {
+  let ClientError::Transport(e) = e else {
+    return false;
+  };
+  matches!(e.downcast_ref::<transport::Error>(), Some(transport::Error::Rejected { status_code: 401 }))
+}
+
+#[async_trait]
+impl BlockFetcher for BitcoinRpcClient {
+  type FetchedBlock = BlockBytes;
+
+  async fn fetch_block(
+    &self,
+    block_hash: &BlockHash,
+  ) -> anyhow::Result<BlockBytes> {
+    let hex: String = self.request("getblock", rpc_params![block_hash.to_string(), 0]).await?;
+    Ok(BlockBytes(Bytes::from(hex::decode(hex)?)))
+  }
+}
+
+#[derive(Deserialize)]
+struct VerboseHeader {
+  height: u32,
+  // -1 when the block is not on the active chain
+  confirmations: i64,
+}
+
+#[async_trait]
+impl HeaderFetcher for BitcoinRpcClient {
+  async fn fetch_headers(&self, from_block_hash: &BlockHash, count: usize) -> anyhow::Result<Box<dyn Send + Iterator<Item = anyhow::Result<bitcoin::block::Header>>>> {
+    let from: VerboseHeader = self.request("getblockheader", rpc_params![from_block_hash.to_string(), true]).await?;
+
+    // like the REST interface, follow the active chain from the given block, giving nothing for a block off it
+    if from.confirmations < 1 {
+      return Ok(Box::new(std::iter::empty()));
+    }
+    let count = (count as i64).min(from.confirmations) as u32;
+    let mut batch = BatchRequestBuilder::new();
+    for height in from.height..from.height + count {
+      batch.insert("getblockhash", rpc_params![height])?;
+    }
+    let hashes = self.batch::<String>(batch).await?;
+
+    let mut batch = BatchRequestBuilder::new();
+    for hash in &hashes {
+      batch.insert("getblockheader", rpc_params![hash, false])?;
+    }
+    let headers = self.batch::<String>(batch).await?;
+
+    Ok(Box::new(headers.into_iter().map(|hex| -> anyhow::Result<bitcoin::block::Header> {
+      let binary = hex::decode(hex)?;
+      Ok(consensus::deserialize(&binary).map_err(
+        |e| anyhow::anyhow!("Failed to deserialize block header: {}", e)
+      )?)
+    })))
+  }
+}
+
+#[async_trait]
+impl HashFetcher for BitcoinRpcClient {
+  async fn fetch_hash(&self, height: u32) -> anyhow::Result<Option<BlockHash>> {
+    match self.request::<String>("getblockhash", rpc_params![height]).await {
+      Ok(hash) => Ok(Some(BlockHash::from_str(&hash)?)),
+      Err(ClientError::Call(e)) if e.code() == RPC_INVALID_PARAMETER => Ok(None),
+      Err(e) => Err(e.into()),
+    }
+  }
+}
+
+#[async_trait]
+impl MempoolFetcher for BitcoinRpcClient {
+  async fn fetch_mempool_txids(&self) -> anyhow::Result<Vec<Txid>> {
+    let txids: Vec<String> = self.request("getrawmempool", rpc_params![]).await?;
+    Ok(txids.iter().map(|txid| Txid::from_str(txid)).collect::<Result<_, _>>()?)
+  }
+
+  async fn fetch_mempool_transaction(&self, txid: &Txid) -> anyhow::Result<Option<Transaction>> {
+    match self.request::<String>("getrawtransaction", rpc_params![txid.to_string(), false]).await {
+      Ok(hex) => Ok(Some(consensus::deserialize(&hex::decode(hex)?).map_err(
+        |e| anyhow::anyhow!("Failed to deserialize transaction: {}", e)
+      )?)),
+      Err(ClientError::Call(e)) if e.code() == RPC_INVALID_ADDRESS_OR_KEY => Ok(None),
+      Err(e) => Err(e.into()),
+    }
+  }
+}
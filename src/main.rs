@@ -1,36 +1,127 @@
 mod store;
 mod scanner;
 mod api;
+mod electrum;
 mod fetch;
 mod iter_util;
+mod script_util;
+mod mempool;
+#[cfg(test)]
+mod test_util;
 
-use std::{convert::Infallible, sync::Arc};
-use clap::Parser;
+use std::{convert::Infallible, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+use anyhow::Context as _;
+use clap::{Parser, Subcommand};
 use opentelemetry_otlp::WithExportConfig as _;
 use tokio::select;
 use tracing_subscriber::{layer::SubscriberExt as _, util::SubscriberInitExt as _};
 use opentelemetry::trace::TracerProvider as _;
 
-use crate::{api::serve, fetch::{blocks_dir::BlocksDirReader, combined::CombinedFetcher, rest_api::BitcoinRestClient}, scanner::scan, store::Store};
+use crate::{api::serve, electrum::serve_electrum, mempool::{track_mempool, Mempool}, fetch::{blocks_dir::BlocksDirReader, combined::CombinedFetcher, node::NodeClient, rest_api::BitcoinRestClient, rpc::{BitcoinRpcClient, RpcAuth}}, scanner::{events, notify::subscribe_blocks, scan}, store::{BlockHeight, Store}};
 
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, subcommand_negates_reqs = true)]
 struct Args {
-  #[arg(long = "rest-url", env = "REST_URL")]
-  rest_url: String,
+  #[command(subcommand)]
+  command: Option<Command>,
+
+  #[command(flatten)]
+  run: RunArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+  /// Rewind the store offline down to the given height, then exit
+  Rewind(RewindArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct RunArgs {
+  #[arg(long = "rest-url", env = "REST_URL", conflicts_with = "rpc_url")]
+  rest_url: Option<String>,
+
+  #[arg(long = "rpc-url", env = "RPC_URL")]
+  rpc_url: Option<String>,
+
+  #[arg(long = "rpc-cookie-file", env = "RPC_COOKIE_FILE", conflicts_with = "rpc_user")]
+  rpc_cookie_file: Option<PathBuf>,
+
+  #[arg(long = "rpc-user", env = "RPC_USER", requires = "rpc_password")]
+  rpc_user: Option<String>,
+
+  #[arg(long = "rpc-password", env = "RPC_PASSWORD", requires = "rpc_user")]
+  rpc_password: Option<String>,
 
   #[arg(long = "blocks-dir", env = "BLOCKS_DIR")]
   blocks_dir: Option<String>,
 
+  #[arg(long = "data-dir", env = "DATA_DIR", required = true)]
+  data_dir: Option<String>,
+
+  #[arg(long = "poll-interval-ms", env = "POLL_INTERVAL_MS", default_value_t = 5000)]
+  poll_interval_ms: u64,
+
+  #[arg(long = "zmq-url", env = "ZMQ_URL")]
+  zmq_url: Option<String>,
+
+  #[arg(long = "network", env = "NETWORK", default_value_t = bitcoin::Network::Bitcoin)]
+  network: bitcoin::Network,
+
+  #[arg(long = "track-mempool", env = "TRACK_MEMPOOL")]
+  track_mempool: bool,
+
+  #[arg(long = "electrum-listen", env = "ELECTRUM_LISTEN")]
+  electrum_listen: Option<SocketAddr>,
+}
+
+#[derive(clap::Args, Debug)]
+struct RewindArgs {
   #[arg(long = "data-dir", env = "DATA_DIR")]
   data_dir: String,
+
+  #[arg(long = "height")]
+  height: BlockHeight,
 }
 
 #[tokio::main]
-async fn main() -> anyhow::Result<Infallible> {
+async fn main() -> anyhow::Result<()> {
   let args = Args::try_parse()?;
 
-  let rest_client = BitcoinRestClient::new(args.rest_url);
+  match args.command {
+    Some(Command::Rewind(args)) => rewind(args),
+    None => match run(args.run).await? {},
+  }
+}
+
+fn rewind(args: RewindArgs) -> anyhow::Result<()> {
+  let store = Store::open(&args.data_dir)?;
+
+  let rewound = store.rewind_to(args.height)?;
+  if rewound.blocks.is_empty() {
+    println!("Nothing to rewind above height {}", args.height);
+  }
+  for block in rewound.blocks {
+    println!("Rewound {}", block);
+  }
+
+  Ok(())
+}
+
+async fn run(args: RunArgs) -> anyhow::Result<Infallible> {
+  let data_dir = args.data_dir.context("--data-dir is required")?;
+
+  let node_client = match (args.rest_url, args.rpc_url) {
+    (Some(rest_url), None) => NodeClient::Rest(BitcoinRestClient::new(rest_url)),
+    (None, Some(rpc_url)) => {
+      let auth = match (args.rpc_cookie_file, args.rpc_user, args.rpc_password) {
+        (Some(cookie_file), _, _) => RpcAuth::CookieFile(cookie_file),
+        (None, Some(user), Some(password)) => RpcAuth::UserPassword { user, password },
+        _ => RpcAuth::None,
+      };
+      NodeClient::Rpc(BitcoinRpcClient::new(rpc_url, auth)?)
+    }
+    _ => anyhow::bail!("exactly one of --rest-url or --rpc-url is required"),
+  };
 
   let blocks_dir = if let Some(blocks_dir) = args.blocks_dir {
     Some(BlocksDirReader::try_open(blocks_dir)?)
@@ -38,9 +129,15 @@ async fn main() -> anyhow::Result<Infallible> {
     None
   };
 
-  let fetcher = Arc::new(CombinedFetcher::new(rest_client, blocks_dir));
+  let fetcher = Arc::new(CombinedFetcher::new(node_client, blocks_dir));
 
-  let store = Arc::new(Store::open(&args.data_dir)?);
+  let block_notifications = if let Some(zmq_url) = args.zmq_url {
+    Some(subscribe_blocks(zmq_url, 16)?)
+  } else {
+    None
+  };
+
+  let store = Arc::new(Store::open(&data_dir)?);
 
   let exporter = opentelemetry_otlp::SpanExporter::builder().with_tonic().with_endpoint("http://localhost:4317").build().unwrap();
 
@@ -59,9 +156,31 @@ async fn main() -> anyhow::Result<Infallible> {
     .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("scanner")))
     .init();
 
+  let poll_interval = Duration::from_millis(args.poll_interval_ms);
+  let mempool = Arc::new(Mempool::default());
+  let events = events::channel();
+
+  let mempool_tracker = async {
+    if args.track_mempool {
+      Ok(track_mempool(mempool.clone(), store.clone(), fetcher.clone(), poll_interval).await)
+    } else {
+      futures::future::pending().await
+    }
+  };
+
+  let electrum_server = async {
+    if let Some(electrum_listen) = args.electrum_listen {
+      serve_electrum(store.clone(), mempool.clone(), fetcher.clone(), electrum_listen, poll_interval).await
+    } else {
+      futures::future::pending().await
+    }
+  };
+
   select! {
-    res = scan(store.clone(), fetcher) => res,
-    res = serve(store.clone()) => res,
+    res = scan(store.clone(), fetcher.clone(), poll_interval, block_notifications, args.network, events.clone()) => res,
+    res = serve(store.clone(), mempool.clone(), args.network, events) => res,
+    res = mempool_tracker => res,
+    res = electrum_server => res,
   }?;
 
   unreachable!();
@@ -0,0 +1,225 @@
+use std::{collections::{BTreeSet, HashMap, HashSet}, convert::Infallible, sync::{Arc, RwLock}, time::Duration};
+
+use bitcoin::{hashes::{sha256, Hash as _}, Amount, OutPoint, ScriptBuf, ScriptHash, Transaction, Txid};
+use futures::{stream, StreamExt as _, TryStreamExt as _};
+use tokio::{task::block_in_place, time::sleep};
+
+use crate::{fetch::MempoolFetcher, store::{tx::TXStoreRead as _, txo::TXOStoreRead as _, BlockHeight, Seek, Store}};
+
+#[derive(Default)]
+pub struct Mempool {
+  scripts: RwLock<HashMap<ScriptHash, ScriptOverlay>>,
+  // SHA256 of the scripts unconfirmed outputs pay to, for Electrum clients watching fresh scripts
+  script_sha256s: RwLock<HashMap<sha256::Hash, ScriptBuf>>,
+}
+
+// unconfirmed activity of a locker script
+#[derive(Default, Clone, PartialEq)]
+pub struct ScriptOverlay {
+  // outputs of unconfirmed transactions paying to the script
+  pub generated_txos: Vec<(OutPoint, Amount)>,
+  // outputs of the script, confirmed or not, spent by unconfirmed transactions
+  pub spent_txos: Vec<(OutPoint, Amount)>,
+  // unconfirmed transactions generating or spending the above
+  pub txids: BTreeSet<Txid>,
+  // those of `txids` spending outputs of other unconfirmed transactions, as of the last build
+  pub txids_with_unconfirmed_inputs: BTreeSet<Txid>,
+}
+
+impl ScriptOverlay {
+  pub fn balance_delta(&self) -> i64 {
+    let generated = self.generated_txos.iter().map(|(_, value)| value.to_sat() as i64).sum::<i64>();
+    let spent = self.spent_txos.iter().map(|(_, value)| value.to_sat() as i64).sum::<i64>();
+    generated - spent
+  }
+
+  pub fn is_spent(&self, outpoint: &OutPoint) -> bool {
+    self.spent_txos.iter().any(|(spent, _)| spent == outpoint)
+  }
+}
+
+impl Mempool {
+  // transactions confirmed since the overlay was last built are left out, so they do not count twice until the next build
+  pub fn get_script_overlay(&self, store: &Store, script_hash: &ScriptHash) -> anyhow::Result<Option<ScriptOverlay>> {
+    let Some(mut overlay) = self.scripts.read().unwrap().get(script_hash).cloned() else {
+      return Ok(None);
+    };
+
+    let mut confirmed_txids = HashSet::new();
+    for txid in &overlay.txids {
+      if store.get_tx_location(txid)?.is_some() {
+        confirmed_txids.insert(*txid);
+      }
+    }
+
+    // a spend is confirmed once the store has the TXO spent, by whichever transaction
+    let mut spent_outpoints = overlay.spent_txos.iter().map(|(outpoint, _)| *outpoint).collect::<Vec<_>>();
+    spent_outpoints.sort();
+    spent_outpoints.dedup();
+    let mut confirmed_spends = HashSet::new();
+    for (outpoint, txo) in spent_outpoints.iter().zip(store.get_txos(spent_outpoints.iter())?) {
+      if txo?.is_some_and(|txo| txo.spent_height.is_some()) {
+        confirmed_spends.insert(*outpoint);
+      }
+    }
+
+    overlay.generated_txos.retain(|(outpoint, _)| !confirmed_txids.contains(&outpoint.txid));
+    overlay.spent_txos.retain(|(outpoint, _)| !confirmed_spends.contains(outpoint));
+    overlay.txids.retain(|txid| !confirmed_txids.contains(txid));
+    overlay.txids_with_unconfirmed_inputs.retain(|txid| !confirmed_txids.contains(txid));
+    Ok(Some(overlay))
+  }
+
+  // rebuilds the overlays from the transactions of the node's mempool, dropping those confirmed since they were listed
+  pub fn update(&self, store: &Store, txs: &mut HashMap<Txid, Transaction>) -> anyhow::Result<()> {
+    let (scripts, script_sha256s) = tracing::trace_span!("mempool").in_scope(|| build_overlay(store, txs))?;
+    *self.scripts.write().unwrap() = scripts;
+    *self.script_sha256s.write().unwrap() = script_sha256s;
+    Ok(())
+  }
+
+  pub fn get_script_hash_by_sha256(&self, script_sha256: &sha256::Hash) -> Option<ScriptHash> {
+    self.script_sha256s.read().unwrap().get(script_sha256).map(|script| script.script_hash())
+  }
+
+  pub fn get_script_by_sha256(&self, script_sha256: &sha256::Hash) -> Option<ScriptBuf> {
+    self.script_sha256s.read().unwrap().get(script_sha256).cloned()
+  }
+
+  // confirmed unspent TXOs of the script ordered by height, then unconfirmed ones without a height,
+  // leaving out those spent by unconfirmed transactions
+  pub fn get_script_utxos(
+    &self,
+    store: &Store,
+    script_hash: &ScriptHash,
+  ) -> anyhow::Result<Vec<(OutPoint, Amount, Option<BlockHeight>)>> {
+    let overlay = self.get_script_overlay(store, script_hash)?.unwrap_or_default();
+
+    let outpoints = store.get_locker_script_unspent_txos(script_hash, Seek::First)?.collect::<anyhow::Result<Vec<_>>>()?;
+    let mut utxos = Vec::with_capacity(outpoints.len() + overlay.generated_txos.len());
+    for (outpoint, txo) in outpoints.iter().zip(store.get_txos(outpoints.iter())?) {
+      let Some(txo) = txo? else {
+        anyhow::bail!("missing txo {}", outpoint);
+      };
+      utxos.push((*outpoint, txo.value, Some(txo.generated_height)));
+    }
+    utxos.sort_by_key(|(outpoint, _, height)| (*height, *outpoint));
+    utxos.extend(overlay.generated_txos.iter().map(|(outpoint, value)| (*outpoint, *value, None)));
+
+    utxos.retain(|(outpoint, _, _)| !overlay.is_spent(outpoint));
+    Ok(utxos)
+  }
+}
+
+// errors are logged and retried at the next poll, the mempool is optional to the indexer
+pub async fn track_mempool<Fetcher: MempoolFetcher>(
+  mempool: Arc<Mempool>,
+  store: Arc<Store>,
+  fetcher: Fetcher,
+  poll_interval: Duration,
+) -> Infallible {
+  let mut txs = HashMap::<Txid, Transaction>::new();
+  loop {
+    if let Err(e) = update_mempool(&mempool, &store, &fetcher, &mut txs).await {
+      tracing::warn!("Failed to update mempool: {:#}", e);
+    }
+
+    sleep(poll_interval).await;
+  }
+}
+
+async fn update_mempool<Fetcher: MempoolFetcher>(
+  mempool: &Mempool,
+  store: &Store,
+  fetcher: &Fetcher,
+  txs: &mut HashMap<Txid, Transaction>,
+) -> anyhow::Result<()> {
+  let transaction_fetch_concurrency = 16;
+
+  let txids = fetcher.fetch_mempool_txids().await?.into_iter().collect::<HashSet<_>>();
+  txs.retain(|txid, _| txids.contains(txid));
+
+  let new_txs = stream::iter(txids.iter().filter(|txid| !txs.contains_key(*txid)))
+    .map(|txid| fetcher.fetch_mempool_transaction(txid))
+    .buffer_unordered(transaction_fetch_concurrency)
+    .try_collect::<Vec<_>>()
+    .await?;
+  for tx in new_txs.into_iter().flatten() {
+    txs.insert(tx.compute_txid(), tx);
+  }
+
+  block_in_place(|| mempool.update(store, txs))
+}
+
+fn build_overlay(
+  store: &Store,
+  txs: &mut HashMap<Txid, Transaction>,
+) -> anyhow::Result<(HashMap<ScriptHash, ScriptOverlay>, HashMap<sha256::Hash, ScriptBuf>)> {
+  // drop transactions the scanner has confirmed since they were listed
+  let mut first_outpoints = txs.keys().map(|txid| OutPoint { txid: *txid, vout: 0 }).collect::<Vec<_>>();
+  first_outpoints.sort();
+  let first_txos = store.get_txos(first_outpoints.iter())?;
+  for (outpoint, txo) in first_outpoints.iter().zip(first_txos) {
+    if txo?.is_some() {
+      txs.remove(&outpoint.txid);
+    }
+  }
+
+  let txids_with_unconfirmed_inputs = txs.iter()
+    .filter(|(_, tx)| tx.input.iter().any(|txin| txs.contains_key(&txin.previous_output.txid)))
+    .map(|(txid, _)| *txid)
+    .collect::<HashSet<_>>();
+
+  let mut scripts = HashMap::<ScriptHash, ScriptOverlay>::new();
+  let mut script_sha256s = HashMap::new();
+  let mut confirmed_prevouts = Vec::new();
+  for (txid, tx) in txs.iter() {
+    for (vout, txout) in tx.output.iter().enumerate() {
+      let outpoint = OutPoint { txid: *txid, vout: vout as u32 };
+      let script_hash = txout.script_pubkey.script_hash();
+      script_sha256s.entry(sha256::Hash::hash(txout.script_pubkey.as_bytes())).or_insert_with(|| txout.script_pubkey.clone());
+      let overlay = scripts.entry(script_hash).or_default();
+      overlay.generated_txos.push((outpoint, txout.value));
+      overlay.txids.insert(*txid);
+    }
+
+    for txin in &tx.input {
+      let prevout = txin.previous_output;
+      match txs.get(&prevout.txid) {
+        Some(parent) => {
+          let Some(txout) = parent.output.get(prevout.vout as usize) else {
+            continue;
+          };
+          let overlay = scripts.entry(txout.script_pubkey.script_hash()).or_default();
+          overlay.spent_txos.push((prevout, txout.value));
+          overlay.txids.insert(*txid);
+        }
+        None => confirmed_prevouts.push((prevout, *txid)),
+      }
+    }
+  }
+
+  // conflicting transactions may spend the same prevout
+  confirmed_prevouts.sort();
+  confirmed_prevouts.dedup();
+  let confirmed_txos = store.get_txos(confirmed_prevouts.iter().map(|(prevout, _)| prevout))?;
+  for ((prevout, txid), txo) in confirmed_prevouts.iter().zip(confirmed_txos) {
+    let Some(txo) = txo? else {
+      continue;
+    };
+    let overlay = scripts.entry(txo.locker_script_hash).or_default();
+    if !overlay.is_spent(prevout) {
+      overlay.spent_txos.push((*prevout, txo.value));
+    }
+    overlay.txids.insert(*txid);
+  }
+
+  for overlay in scripts.values_mut() {
+    overlay.txids_with_unconfirmed_inputs = overlay.txids.iter()
+      .filter(|txid| txids_with_unconfirmed_inputs.contains(*txid))
+      .copied()
+      .collect();
+  }
+
+  Ok((scripts, script_sha256s))
+}
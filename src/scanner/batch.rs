@@ -1,13 +1,20 @@
-use bitcoin::OutPoint;
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator as _};
+use std::collections::{HashMap, HashSet};
+
+use bitcoin::{hashes::Hash as _, Amount, OutPoint, ScriptBuf, ScriptHash, SignedAmount, Txid};
+use rayon::iter::{IndexedParallelIterator as _, IntoParallelRefIterator, ParallelIterator as _};
 use tracing::instrument;
 
-use crate::store::{self, block::{BlockStoreRead as _, BlockStoreWrite as _}, txo::{TXOGenerated, TXOSpent, TXOStoreWrite}, BlockHeight};
+use crate::{scanner::{events::ConnectedBlocks, ReorgDetected}, store::{self, balance::BalanceStoreWrite as _, history::HistoryStoreWrite as _, block::{BlockStoreRead as _, BlockStoreWrite as _}, script::ScriptStoreWrite as _, tx::{TXLocation, TXStoreWrite as _}, txo::{TXOGenerated, TXOSpent, TXOStoreRead as _, TXOStoreWrite}, BlockHeight}};
 
 pub struct Batch {
   pub(crate) start_height: BlockHeight,
   pub(crate) end_height: BlockHeight,
+  pub(crate) prev_block_hash: bitcoin::BlockHash,
   pub(crate) blocks: Vec<bitcoin::BlockHash>,
+  pub(crate) block_times: Vec<u32>,
+  pub(crate) txs: Vec<(Txid, TXLocation)>,
+  // locker scripts of the generated TXOs, each once
+  pub(crate) scripts: HashMap<ScriptHash, ScriptBuf>,
 
   pub(crate) generated_txos: Vec<(OutPoint, TXOGenerated)>,
   pub(crate) spent_txos: Vec<(OutPoint, TXOSpent)>,
@@ -29,7 +36,11 @@ impl Batch {
     let mut batch = Batch {
       start_height,
       end_height: start_height + blocks.len() as BlockHeight,
+      prev_block_hash: blocks.first().map_or(bitcoin::BlockHash::all_zeros(), |block| block.header.prev_blockhash),
       blocks: Vec::with_capacity(blocks.len()),
+      block_times: Vec::with_capacity(blocks.len()),
+      txs: Vec::new(),
+      scripts: HashMap::new(),
       generated_txos: Vec::new(),
       spent_txos: Vec::new(),
     };
@@ -52,8 +63,14 @@ impl Batch {
     height: BlockHeight,
     block: &bitcoin::Block,
   ) -> anyhow::Result<()> {
+    if let Some(prev_block_hash) = self.blocks.last() {
+      if block.header.prev_blockhash != *prev_block_hash {
+        return Err(ReorgDetected { height }.into());
+      }
+    }
     self.scan_transactions(height, block)?;
     self.blocks.push(block.block_hash());
+    self.block_times.push(block.header.time);
     Ok(())
   }
 
@@ -62,23 +79,32 @@ impl Batch {
     height: BlockHeight,
     block: &bitcoin::Block,
   ) -> anyhow::Result<()> {
-    for tx in &block.txdata {
-      for txin in &tx.input {
+    for (position, tx) in block.txdata.iter().enumerate() {
+      let txid = tx.compute_txid();
+      self.txs.push((txid, TXLocation {
+        height,
+        position: position as u32,
+      }));
+
+      for (vin, txin) in tx.input.iter().enumerate() {
         if txin.previous_output.is_null() {
           continue;
         }
 
-        self.spent_txos.push((txin.previous_output, TXOSpent { spent_height: height }));
+        self.spent_txos.push((txin.previous_output, TXOSpent {
+          spent_height: height,
+          spending_txid: txid,
+          spending_vin: vin as u32,
+        }));
       }
 
-      let txid = tx.compute_txid();
-
       for (index, txout) in tx.output.iter().enumerate() {
         let outpoint = OutPoint {
           txid,
           vout: index as u32,
         };
         let locker_script_hash = txout.script_pubkey.script_hash();
+        self.scripts.entry(locker_script_hash).or_insert_with(|| txout.script_pubkey.clone());
 
         self.generated_txos.push((
           outpoint,
@@ -93,7 +119,8 @@ impl Batch {
     Ok(())
   }
 
-  pub fn write(self, store: &mut store::Batch) -> anyhow::Result<()> {
+  // returns what was written, for publishing once committed
+  pub fn write(mut self, store: &mut store::Batch) -> anyhow::Result<ConnectedBlocks> {
     match store.store.get_tip_block()? {
       Some((tip_height, _)) if tip_height + 1 != self.start_height => {
         anyhow::bail!(
@@ -102,6 +129,9 @@ impl Batch {
           tip_height,
         );
       }
+      Some((_, tip_hash)) if tip_hash != self.prev_block_hash => {
+        return Err(ReorgDetected { height: self.start_height }.into());
+      }
       None if self.start_height != 0 => {
         anyhow::bail!(
           "Batch start height {} is invalid for empty store",
@@ -111,15 +141,152 @@ impl Batch {
       _ => {}
     }
 
+    self.drop_duplicate_coinbase_txos(store.store)?;
+
     store.insert_blocks(self.blocks.iter().enumerate().map(|(i, block_hash)| {
       let block_height = self.start_height + i as BlockHeight;
       (block_hash, block_height)
     }));
 
+    let block_times = store.store.derive_block_times(self.start_height, self.block_times.iter().copied())?;
+    store.insert_block_times(block_times.iter().enumerate().map(|(i, block_time)| {
+      (self.start_height + i as BlockHeight, block_time)
+    }));
+
+    store.insert_txs(self.txs.iter().map(|(txid, location)| (txid, location)));
+
+    store.insert_scripts(self.scripts.iter());
+
     store.generated_txos(self.generated_txos.par_iter().map(|(outpoint, txo)| (outpoint, txo)));
 
-    store.spent_txos(self.spent_txos.par_iter().map(|(outpoint, txo)| (outpoint, txo)));
+    let spent_txos = self.resolve_spent_txos(store.store)?;
+    store.spent_txos(self.spent_txos.par_iter().zip(spent_txos.par_iter()).map(
+      |((outpoint, txo), (locker_script_hash, _))| (outpoint, txo, locker_script_hash)
+    ));
+
+    store.add_balance_deltas(
+      self.generated_txos.iter()
+        .map(|(_, txo)| (&txo.locker_script_hash, txo.generated_height, to_signed(txo.value)))
+        .chain(self.spent_txos.iter().zip(spent_txos.iter()).map(
+          |((_, txo), (locker_script_hash, value))| (locker_script_hash, txo.spent_height, -to_signed(*value))
+        ))
+    )?;
+
+    let script_transactions = self.script_transactions(&spent_txos);
+    store.add_script_transactions(script_transactions.iter().map(
+      |(locker_script_hash, location, txid, delta)| (locker_script_hash, *location, txid, *delta)
+    ));
+
+    Ok(ConnectedBlocks {
+      blocks: self.blocks.iter().enumerate().map(|(i, block_hash)| (self.start_height + i as BlockHeight, *block_hash)).collect(),
+      generated_txos: self.generated_txos,
+      spent_txos: self.spent_txos.into_iter().zip(spent_txos).map(
+        |((outpoint, txo), (locker_script_hash, value))| (outpoint, txo, locker_script_hash, value)
+      ).collect(),
+    })
+  }
+
+  // A coinbase repeating the txid of an earlier one (BIP30, heights 91842 and 91880) generates nothing, the TXO
+  // merge keeps the first TXOs, so neither the TXO indexes nor the balances see the repeated ones. Its location
+  // is left out too, the txid keeps locating the first one.
+  #[instrument(name = "Batch::drop_duplicate_coinbase_txos", level="trace", skip_all)]
+  pub(crate) fn drop_duplicate_coinbase_txos(&mut self, store: &store::Store) -> anyhow::Result<()> {
+    let coinbase_txids = self.txs.iter()
+      .filter(|(_, location)| location.position == 0)
+      .map(|(txid, _)| *txid)
+      .collect::<HashSet<_>>();
+
+    let mut coinbase_outpoints = self.generated_txos.iter()
+      .map(|(outpoint, _)| *outpoint)
+      .filter(|outpoint| coinbase_txids.contains(&outpoint.txid))
+      .collect::<Vec<_>>();
+    coinbase_outpoints.sort();
+    coinbase_outpoints.dedup();
 
+    // backfilled blocks find their own TXOs stored
+    let mut existing_txids = HashSet::new();
+    for (outpoint, txo) in coinbase_outpoints.iter().zip(store.get_txos(coinbase_outpoints.iter())?) {
+      if txo?.is_some_and(|txo| txo.generated_height < self.start_height) {
+        existing_txids.insert(outpoint.txid);
+      }
+    }
+
+    // repeats within the batch keep the first one too
+    let mut kept_txids = HashSet::new();
+    self.txs.retain(|(txid, location)| {
+      location.position != 0 || (!existing_txids.contains(txid) && kept_txids.insert(*txid))
+    });
+    let mut kept_outpoints = HashSet::new();
+    self.generated_txos.retain(|(outpoint, _)| {
+      !coinbase_txids.contains(&outpoint.txid) || (!existing_txids.contains(&outpoint.txid) && kept_outpoints.insert(*outpoint))
+    });
     Ok(())
   }
+
+  // transactions of the locker scripts with their net effect on each, given the resolved spent TXOs
+  pub(crate) fn script_transactions(&self, spent_txos: &[(ScriptHash, Amount)]) -> Vec<(ScriptHash, TXLocation, Txid, SignedAmount)> {
+    let locations = self.txs.iter().copied().collect::<HashMap<_, _>>();
+    self.generated_txos.iter()
+      .map(|(outpoint, txo)| (txo.locker_script_hash, locations[&outpoint.txid], outpoint.txid, to_signed(txo.value)))
+      .chain(self.spent_txos.iter().zip(spent_txos).map(
+        |((_, txo), (locker_script_hash, value))| (*locker_script_hash, locations[&txo.spending_txid], txo.spending_txid, -to_signed(*value))
+      ))
+      .collect()
+  }
+
+  // the unspent outpoint, balance and script transaction indexes are keyed by locker script, which spends do not carry
+  #[instrument(name = "Batch::resolve_spent_txos", level="trace", skip_all)]
+  pub(crate) fn resolve_spent_txos(&self, store: &store::Store) -> anyhow::Result<Vec<(ScriptHash, Amount)>> {
+    let mut locker_script_hashes = self.generated_txos.iter()
+      .map(|(outpoint, txo)| (*outpoint, (txo.locker_script_hash, txo.value)))
+      .collect::<HashMap<_, _>>();
+
+    let mut stored_outpoints = self.spent_txos.iter()
+      .map(|(outpoint, _)| *outpoint)
+      .filter(|outpoint| !locker_script_hashes.contains_key(outpoint))
+      .collect::<Vec<_>>();
+    stored_outpoints.sort();
+    stored_outpoints.dedup();
+
+    let stored_txos = store.get_txos(stored_outpoints.iter())?;
+    for (outpoint, txo) in stored_outpoints.iter().zip(stored_txos) {
+      let Some(txo) = txo? else {
+        anyhow::bail!("missing txo {} spent in batch starting at height {}", outpoint, self.start_height);
+      };
+      locker_script_hashes.insert(*outpoint, (txo.locker_script_hash, txo.value));
+    }
+
+    Ok(self.spent_txos.iter().map(|(outpoint, _)| locker_script_hashes[outpoint]).collect())
+  }
+}
+
+fn to_signed(value: Amount) -> SignedAmount {
+  // amounts never exceed the 21M BTC supply, far within i64
+  SignedAmount::from_sat(value.to_sat() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+  use bitcoin::Amount;
+
+  use crate::{store::{balance::BalanceStoreRead as _, tx::{TXLocation, TXStoreRead as _}, txo::TXOStoreRead as _, Seek}, test_util::{coinbase, other_script, outpoint, script, TestStore}};
+
+  #[test]
+  fn duplicate_coinbase_generates_nothing() {
+    let test_store = TestStore::open("duplicate-coinbase");
+    let duplicated = coinbase(0, &[(script(), 50)]);
+    test_store.connect_block(vec![duplicated.clone()]);
+    test_store.connect_block(vec![coinbase(1, &[(other_script(), 50)])]);
+    let connected = test_store.connect_block(vec![duplicated.clone()]);
+
+    assert!(connected.generated_txos.is_empty());
+    let store = test_store.store();
+    let txo = store.get_txos([outpoint(&duplicated, 0)].iter()).unwrap().next().unwrap().unwrap().unwrap();
+    assert_eq!(txo.generated_height, 0);
+    assert_eq!(store.get_tx_location(&duplicated.compute_txid()).unwrap(), Some(TXLocation { height: 0, position: 0 }));
+    let balance_history = store.get_balance_history(&script().script_hash(), Seek::First).unwrap()
+      .collect::<anyhow::Result<Vec<_>>>().unwrap();
+    assert_eq!(balance_history, vec![(0, Amount::from_sat(50))]);
+    assert_eq!(store.get_balance_at(&script().script_hash(), 2).unwrap(), Amount::from_sat(50));
+  }
 }
@@ -0,0 +1,39 @@
+use std::sync::Arc;
+
+use bitcoin::{Amount, BlockHash, OutPoint, ScriptHash};
+use tokio::sync::broadcast;
+
+use crate::store::{rewind::Rewound, txo::{TXOGenerated, TXOSpent}, BlockHeight};
+
+// Published by the scanner once the store has committed the change, so consumers reading the store
+// upon an event see it. Events are dropped while nobody listens.
+pub type ScanEvents = broadcast::Sender<Arc<ScanEvent>>;
+
+// subscribers lagging further behind miss events
+const CAPACITY: usize = 64;
+
+pub fn channel() -> ScanEvents {
+  broadcast::channel(CAPACITY).0
+}
+
+pub enum ScanEvent {
+  Connected(ConnectedBlocks),
+  // blocks of a stale chain rewound from the store tip
+  Disconnected(Rewound),
+}
+
+// blocks appended to the store tip with the TXOs they generated and spent
+pub struct ConnectedBlocks {
+  // ascending height
+  pub blocks: Vec<(BlockHeight, BlockHash)>,
+  pub generated_txos: Vec<(OutPoint, TXOGenerated)>,
+  // along with the locker script hash and value of the spent TXO
+  pub spent_txos: Vec<(OutPoint, TXOSpent, ScriptHash, Amount)>,
+}
+
+pub fn publish(events: &ScanEvents, event: impl FnOnce() -> ScanEvent) {
+  if events.receiver_count() > 0 {
+    // receivers may have gone since they were counted
+    _ = events.send(Arc::new(event()));
+  }
+}
@@ -8,11 +8,12 @@ use crate::fetch::{BlockFetcher, HeaderFetcher};
 pub fn stream_block_header_batches<Fetcher: HeaderFetcher>(
   fetcher: Fetcher,
   start_hash: bitcoin::BlockHash,
+  skip_start: bool,
   batch_size: usize,
 ) -> impl Stream<Item = anyhow::Result<Vec<bitcoin::block::Header>>> {
   try_stream! {
     let mut next_hash = start_hash;
-    let mut skip_first = false;
+    let mut skip_first = skip_start;
     loop {
       let mut headers = fetcher.fetch_headers(&next_hash, batch_size).await?;
 
@@ -43,12 +44,13 @@ pub fn stream_block_header_batches<Fetcher: HeaderFetcher>(
 pub fn prefetch_block_headers<Fetcher: HeaderFetcher + Send + 'static>(
   fetcher: Fetcher,
   start_hash: bitcoin::BlockHash,
+  skip_start: bool,
   batch_size: usize,
   batch_buffer: usize,
 ) -> impl Stream<Item = anyhow::Result<bitcoin::block::Header>> {
   let (sender, mut receiver) = mpsc::channel(batch_buffer);
   tokio::spawn(async move {
-    let header_batches = stream_block_header_batches(fetcher, start_hash, batch_size);
+    let header_batches = stream_block_header_batches(fetcher, start_hash, skip_start, batch_size);
     tokio::pin!(header_batches);
 
     while let Some(batch) = header_batches.next().await {
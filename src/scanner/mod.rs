@@ -1,30 +1,238 @@
-mod batch;
+pub(crate) mod batch;
 mod fetch;
+pub mod events;
+pub mod notify;
 
-use std::{convert::Infallible, sync::Arc};
+use std::{convert::Infallible, fmt, sync::Arc, time::Duration};
 use futures::{StreamExt, TryStreamExt as _, stream};
-use tokio::{sync::mpsc, task::{block_in_place, spawn_blocking}};
+use tokio::{sync::{mpsc, Mutex}, task::{block_in_place, spawn_blocking}, time::{sleep, timeout}};
+use rayon::iter::{IntoParallelRefIterator as _, ParallelIterator as _};
 
-use crate::{fetch::{BlockFetcher, HashFetcher, HeaderFetcher}, scanner::{batch::Batch, fetch::{prefetch_block_headers, stream_blocks}}, store::{self, block::BlockStoreRead as _, Store}};
+use crate::{fetch::{BlockFetcher, HashFetcher, HeaderFetcher}, scanner::{batch::Batch, events::{publish, ScanEvent, ScanEvents}, fetch::{prefetch_block_headers, stream_blocks}, notify::{BlockNotification, BlockNotifications}}, store::{self, block::{BlockStoreRead as _, BlockStoreWrite as _}, history::HistoryStoreWrite as _, script::ScriptStoreWrite as _, tx::TXStoreWrite as _, txo::TXOStoreWrite as _, BlockHeight, Store}};
 
+#[derive(Debug)]
+pub struct ReorgDetected {
+  pub height: BlockHeight,
+}
+
+impl fmt::Display for ReorgDetected {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "block at height {} does not extend the scanned chain", self.height)
+  }
+}
+
+impl std::error::Error for ReorgDetected {}
 
 pub struct Scanner<Fetcher> {
   fetcher: Fetcher,
   store: Arc<Store>,
+  poll_interval: Duration,
+  block_notifications: Mutex<Option<BlockNotifications>>,
+  network: bitcoin::Network,
+  events: ScanEvents,
 }
 
 impl<Fetcher> Scanner<Fetcher> {
   pub fn open(
     fetcher: Fetcher,
     store: Arc<Store>,
+    poll_interval: Duration,
+    block_notifications: Option<BlockNotifications>,
+    network: bitcoin::Network,
+    events: ScanEvents,
   ) -> anyhow::Result<Self> {
     Ok(Self {
       fetcher,
       store,
+      poll_interval,
+      block_notifications: Mutex::new(block_notifications),
+      network,
+      events,
     })
   }
 
   pub async fn scan_blocks(&self) -> anyhow::Result<Infallible>
+  where
+    Fetcher: HeaderFetcher + BlockFetcher + HashFetcher + Clone + Send + 'static,
+  {
+    self.check_network().await?;
+
+    self.rewind_stale_blocks().await?;
+
+    self.backfill_blocks().await?;
+
+    loop {
+      match self.scan_new_blocks().await {
+        Err(e) if e.is::<ReorgDetected>() => {
+          println!("Chain reorganization detected: {}", e);
+          self.rewind_stale_blocks().await?;
+        }
+        Err(e) => return Err(e),
+        Ok(()) => {
+          // the header stream also ends when the scanned tip is no longer on the node's chain
+          if !self.rewind_stale_blocks().await? {
+            break;
+          }
+        }
+      }
+    }
+
+    println!("Caught up with the node, following the chain tip");
+
+    let mut block_notifications = self.block_notifications.lock().await;
+    loop {
+      match self.scan_next_block().await {
+        Ok(true) => {}
+        Ok(false) => {
+          // the node's chain may also have been replaced by a shorter one
+          if !self.rewind_stale_blocks().await? {
+            if let Some(block) = self.wait_for_block(&mut block_notifications).await {
+              self.scan_notified_block(block).await?;
+            }
+          }
+        }
+        Err(e) if e.is::<ReorgDetected>() => {
+          println!("Chain reorganization detected: {}", e);
+          self.rewind_stale_blocks().await?;
+        }
+        Err(e) => return Err(e),
+      }
+    }
+  }
+
+  // makes sure both the node and the data dir are on the configured network
+  async fn check_network(&self) -> anyhow::Result<()>
+  where
+    Fetcher: HashFetcher,
+  {
+    let genesis_hash = bitcoin::constants::genesis_block(self.network).block_hash();
+
+    let node_genesis_hash = self.fetcher.fetch_hash(0).await?;
+    if node_genesis_hash != Some(genesis_hash) {
+      anyhow::bail!(
+        "node genesis block {:?} does not match {} genesis block {}",
+        node_genesis_hash,
+        self.network,
+        genesis_hash,
+      );
+    }
+
+    let scanned_genesis_hash = block_in_place(||{
+      self.store.get_block_hash(0)
+    })?;
+    if let Some(scanned_genesis_hash) = scanned_genesis_hash {
+      if scanned_genesis_hash != genesis_hash {
+        anyhow::bail!(
+          "data dir genesis block {} does not match {} genesis block {}",
+          scanned_genesis_hash,
+          self.network,
+          genesis_hash,
+        );
+      }
+    }
+
+    Ok(())
+  }
+
+  // fills in the indexes missing from data dirs scanned before they existed
+  async fn backfill_blocks(&self) -> anyhow::Result<()>
+  where
+    Fetcher: BlockFetcher,
+  {
+    let block_fetch_concurrency = 2;
+
+    let Some(heights) = block_in_place(||{
+      self.store.get_block_backfill()
+    })? else {
+      return Ok(());
+    };
+    println!("Backfilling indexes of blocks {} to {}", heights.start, heights.end - 1);
+
+    let end_height = heights.end;
+    let blocks = stream::iter(heights)
+      .map(|height| async move {
+        // heights rewound since the backfill started have nothing left to fill in
+        let Some(block_hash) = block_in_place(||{
+          self.store.get_block_hash(height)
+        })? else {
+          return Ok(None);
+        };
+        let block = self.fetcher.fetch_block(&block_hash).await?;
+        let block: bitcoin::Block = block_in_place(|| block.try_into())?;
+        Ok::<_, anyhow::Error>(Some((height, block)))
+      })
+      .buffered(block_fetch_concurrency);
+    tokio::pin!(blocks);
+
+    while let Some((height, block)) = blocks.try_next().await?.flatten() {
+      let store = self.store.clone();
+      spawn_blocking(move || {
+        let mut batch = tracing::trace_span!("batch").in_scope(|| Batch::build(height, vec![block]))?;
+        batch.drop_duplicate_coinbase_txos(&store)?;
+        let spent_txos = batch.resolve_spent_txos(&store)?;
+        let mut tx = store::Batch {
+          store: &store,
+          batch: rocksdb::WriteBatch::default(),
+        };
+        tx.add_script_transactions(batch.script_transactions(&spent_txos).iter().map(
+          |(locker_script_hash, location, txid, delta)| (locker_script_hash, *location, txid, *delta)
+        ));
+        tx.record_spenders(batch.spent_txos.par_iter().map(|(outpoint, spent)| (outpoint, spent)));
+        tx.insert_txs(batch.txs.iter().map(|(txid, location)| (txid, location)));
+        tx.insert_scripts(batch.scripts.iter());
+        // blocks are backfilled in order, so the preceding block times are already stored
+        let block_times = store.derive_block_times(height, batch.block_times.iter().copied())?;
+        tx.insert_block_times(block_times.iter().map(|block_time| (height, block_time)));
+        tx.set_block_backfill(Some(height + 1..end_height));
+        tx.commit()
+      }).await??;
+
+      if (height + 1) % 10_000 == 0 {
+        println!("Backfilled indexes up to {}", height);
+      }
+    }
+
+    let store = self.store.clone();
+    spawn_blocking(move || {
+      let mut tx = store::Batch {
+        store: &store,
+        batch: rocksdb::WriteBatch::default(),
+      };
+      tx.set_block_backfill(None);
+      tx.commit()
+    }).await??;
+
+    println!("Backfilled indexes");
+    Ok(())
+  }
+
+  // scans the block following the store tip, if the node has it, returns whether a block was scanned
+  async fn scan_next_block(&self) -> anyhow::Result<bool>
+  where
+    Fetcher: BlockFetcher + HashFetcher,
+  {
+    let height = block_in_place(||{
+      self.store.get_tip_block()
+    })?.map_or(0, |(height, _)| height + 1);
+
+    let Some(block_hash) = self.fetcher.fetch_hash(height).await? else {
+      return Ok(false);
+    };
+    let block = self.fetcher.fetch_block(&block_hash).await?;
+    let block: bitcoin::Block = block_in_place(|| block.try_into())?;
+
+    let store = self.store.clone();
+    let events = self.events.clone();
+    spawn_blocking(move || {
+      let batch = tracing::trace_span!("batch").in_scope(|| Batch::build(height, vec![block]))?;
+      write_batch(&store, batch, &events)
+    }).await??;
+
+    println!("Scanned block {} at height {}", block_hash, height);
+    Ok(true)
+  }
+
+  async fn scan_new_blocks(&self) -> anyhow::Result<()>
   where
     Fetcher: HeaderFetcher + BlockFetcher + HashFetcher + Clone + Send + 'static,
   {
@@ -34,13 +242,21 @@ impl<Fetcher> Scanner<Fetcher> {
     let block_batch_size = 100;
     let block_batch_concurrency = num_cpus::get();
 
-    let start_height = block_in_place(||{
+    let tip = block_in_place(||{
       self.store.get_tip_block()
-    })?.map_or(0, |(height, _)| height + 1);
+    })?;
 
-    let start_hash = self.fetcher.fetch_hash(start_height).await?;
+    let (start_height, start_hash, skip_start) = match tip {
+      Some((tip_height, tip_hash)) => (tip_height + 1, tip_hash, true),
+      None => {
+        let Some(genesis_hash) = self.fetcher.fetch_hash(0).await? else {
+          anyhow::bail!("node has no genesis block");
+        };
+        (0, genesis_hash, false)
+      }
+    };
 
-    let headers = prefetch_block_headers(self.fetcher.clone(), start_hash, header_batch_size, header_batch_buffer_size);
+    let headers = prefetch_block_headers(self.fetcher.clone(), start_hash, skip_start, header_batch_size, header_batch_buffer_size);
 
     let blocks = stream_blocks(self.fetcher.clone(), headers, block_fetch_concurrency);
 
@@ -76,6 +292,7 @@ impl<Fetcher> Scanner<Fetcher> {
     )).buffered(block_batch_concurrency);
 
     let store = self.store.clone();
+    let events = self.events.clone();
 
     tokio::spawn(async move {
       tokio::pin!(batches);
@@ -84,27 +301,180 @@ impl<Fetcher> Scanner<Fetcher> {
         let batch = batch?;
         let end_height = batch.end_height;
         let store = store.clone();
-        spawn_blocking(move || {
-          let mut tx = store::Batch {
-            store: &store,
-            batch: rocksdb::WriteBatch::default(),
-          };
-          tracing::trace_span!("write").in_scope(|| batch.write(&mut tx))?;
-          tracing::trace_span!("commit").in_scope(|| tx.commit())
-        }).await??;
+        let events = events.clone();
+        spawn_blocking(move || write_batch(&store, batch, &events)).await??;
 
         println!("Scanned blocks up to {}", end_height);
       }
       Ok::<(), anyhow::Error>(())
     }).await??;
 
-    unreachable!();
+    Ok(())
+  }
+
+  // waits for the node to announce a block, or for the poll interval to pass without notifications
+  async fn wait_for_block(&self, block_notifications: &mut Option<BlockNotifications>) -> Option<bitcoin::Block> {
+    let Some(receiver) = block_notifications else {
+      sleep(self.poll_interval).await;
+      return None;
+    };
+
+    match timeout(self.poll_interval, receiver.recv()).await {
+      // quiet socket, poll the node as usual
+      Err(_) => None,
+      Ok(Some(Ok(BlockNotification::Raw(block)))) => Some(block),
+      Ok(Some(Ok(BlockNotification::Hash(block_hash)))) => {
+        tracing::debug!("block {} announced", block_hash);
+        None
+      }
+      Ok(Some(Err(e))) => {
+        println!("Block notifications failed, falling back to polling: {}", e);
+        *block_notifications = None;
+        None
+      }
+      Ok(None) => {
+        println!("Block notifications closed, falling back to polling");
+        *block_notifications = None;
+        None
+      }
+    }
+  }
+
+  // scans a block pushed by the node if it extends the store tip, anything else is left to polling
+  async fn scan_notified_block(&self, block: bitcoin::Block) -> anyhow::Result<()> {
+    let Some((tip_height, tip_hash)) = block_in_place(||{
+      self.store.get_tip_block()
+    })? else {
+      return Ok(());
+    };
+    if block.header.prev_blockhash != tip_hash {
+      return Ok(());
+    }
+
+    let height = tip_height + 1;
+    let block_hash = block.block_hash();
+    let store = self.store.clone();
+    let events = self.events.clone();
+    spawn_blocking(move || {
+      let batch = tracing::trace_span!("batch").in_scope(|| Batch::build(height, vec![block]))?;
+      write_batch(&store, batch, &events)
+    }).await??;
+
+    println!("Scanned notified block {} at height {}", block_hash, height);
+    Ok(())
+  }
+
+  // rewinds the store down to the last block shared with the node's chain, returns whether anything was rewound
+  async fn rewind_stale_blocks(&self) -> anyhow::Result<bool>
+  where
+    Fetcher: HashFetcher,
+  {
+    let Some((tip_height, _)) = block_in_place(||{
+      self.store.get_tip_block()
+    })? else {
+      return Ok(false);
+    };
+
+    let mut height = tip_height;
+    loop {
+      let Some(scanned_hash) = block_in_place(||{
+        self.store.get_block_hash(height)
+      })? else {
+        anyhow::bail!("missing block hash at height {}", height);
+      };
+      if self.fetcher.fetch_hash(height).await? == Some(scanned_hash) {
+        break;
+      }
+      if height == 0 {
+        anyhow::bail!("scanned genesis block {} is not on the node's chain", scanned_hash);
+      }
+      height -= 1;
+    }
+
+    if height == tip_height {
+      return Ok(false);
+    }
+
+    println!("Rewinding blocks from {} down to fork point {}", tip_height, height);
+    let store = self.store.clone();
+    let rewound = spawn_blocking(move || {
+      tracing::trace_span!("rewind").in_scope(|| store.rewind_to(height))
+    }).await??;
+
+    for block in &rewound.blocks {
+      println!("Rewound {}", block);
+    }
+    publish(&self.events, || ScanEvent::Disconnected(rewound));
+    Ok(true)
   }
 }
 
-pub async fn scan<Fetcher: HeaderFetcher + BlockFetcher + HashFetcher + Clone + Send + 'static>(store: Arc<Store>, fetcher: Fetcher) -> anyhow::Result<Infallible> {
-  let scanner = Scanner::open(fetcher, store)?;
+fn write_batch(store: &Store, batch: Batch, events: &ScanEvents) -> anyhow::Result<()> {
+  let mut tx = store::Batch {
+    store,
+    batch: rocksdb::WriteBatch::default(),
+  };
+  let connected = tracing::trace_span!("write").in_scope(|| batch.write(&mut tx))?;
+  tracing::trace_span!("commit").in_scope(|| tx.commit())?;
+  publish(events, || ScanEvent::Connected(connected));
+  Ok(())
+}
+
+pub async fn scan<Fetcher: HeaderFetcher + BlockFetcher + HashFetcher + Clone + Send + 'static>(
+  store: Arc<Store>,
+  fetcher: Fetcher,
+  poll_interval: Duration,
+  block_notifications: Option<BlockNotifications>,
+  network: bitcoin::Network,
+  events: ScanEvents,
+) -> anyhow::Result<Infallible> {
+  let scanner = Scanner::open(fetcher, store, poll_interval, block_notifications, network, events)?;
   scanner.scan_blocks().await?;
 
   unreachable!();
 }
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use async_trait::async_trait;
+  use bitcoin::BlockHash;
+
+  use super::{batch::Batch, events, ReorgDetected, Scanner};
+  use crate::{fetch::HashFetcher, store::{self, block::BlockStoreRead as _}, test_util::{block, coinbase, script, TestStore}};
+
+  // a node whose chain is made of these blocks
+  struct Chain(Vec<BlockHash>);
+
+  #[async_trait]
+  impl HashFetcher for Chain {
+    async fn fetch_hash(&self, height: u32) -> anyhow::Result<Option<BlockHash>> {
+      Ok(self.0.get(height as usize).copied())
+    }
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn reorg_rewinds_to_the_fork_point() {
+    let test_store = TestStore::open("reorg");
+    let (_, hash0) = test_store.connect_block(vec![coinbase(0, &[(script(), 50)])]).blocks[0];
+    test_store.connect_block(vec![coinbase(1, &[(script(), 50)])]);
+
+    // the node replaced the block at height 1, so its next block does not extend the scanned one
+    let block1 = block(1, hash0, vec![coinbase(1, &[(script(), 25)])]);
+    let block2 = block(2, block1.block_hash(), vec![coinbase(2, &[(script(), 50)])]);
+    let chain = Chain(vec![hash0, block1.block_hash(), block2.block_hash()]);
+    let mut batch = store::Batch {
+      store: test_store.store(),
+      batch: rocksdb::WriteBatch::default(),
+    };
+    let e = Batch::build(2, vec![block2]).unwrap().write(&mut batch).err().unwrap();
+    assert_eq!(e.downcast_ref::<ReorgDetected>().map(|reorg| reorg.height), Some(2));
+
+    let scanner = Scanner::open(chain, test_store.store().clone(), Duration::from_secs(1), None, bitcoin::Network::Regtest, events::channel()).unwrap();
+    assert!(scanner.rewind_stale_blocks().await.unwrap());
+    assert_eq!(test_store.store().get_tip_block().unwrap(), Some((0, hash0)));
+    // nothing is left to rewind once on the node's chain
+    assert!(!scanner.rewind_stale_blocks().await.unwrap());
+  }
+}
@@ -0,0 +1,118 @@
+use bitcoin::{consensus, hashes::Hash as _, Block, BlockHash};
+use tokio::sync::mpsc;
+
+// See: https://github.com/bitcoin/bitcoin/blob/0eeae4d174a41c3fc2eae41e76b929fa3114d6f3/doc/zmq.md
+
+pub enum BlockNotification {
+  Hash(BlockHash),
+  Raw(Block),
+}
+
+pub type BlockNotifications = mpsc::Receiver<anyhow::Result<BlockNotification>>;
+
+pub fn subscribe_blocks(
+  url: String,
+  buffer: usize,
+) -> anyhow::Result<BlockNotifications> {
+  let context = zmq::Context::new();
+  let socket = context.socket(zmq::SUB)?;
+  socket.connect(&url)?;
+  socket.set_subscribe(b"hashblock")?;
+  socket.set_subscribe(b"rawblock")?;
+
+  let (sender, receiver) = mpsc::channel(buffer);
+  std::thread::spawn(move || {
+    let _context = context;
+    loop {
+      let notification = socket.recv_multipart(0)
+        .map_err(anyhow::Error::from)
+        .and_then(|parts| parse_notification(&parts));
+      let failed = notification.is_err();
+      if sender.blocking_send(notification).is_err() || failed {
+        break;
+      }
+    }
+  });
+
+  Ok(receiver)
+}
+
+fn parse_notification(parts: &[Vec<u8>]) -> anyhow::Result<BlockNotification> {
+  // [topic, body, sequence number]
+  let [topic, body, sequence] = parts else {
+    anyhow::bail!("malformed ZMQ notification with {} parts", parts.len());
+  };
+  if sequence.len() != 4 {
+    anyhow::bail!("malformed ZMQ notification sequence number of {} bytes", sequence.len());
+  }
+
+  match topic.as_slice() {
+    b"hashblock" => {
+      let mut bytes: [u8; 32] = body.as_slice().try_into()?;
+      // published in display order
+      bytes.reverse();
+      Ok(BlockNotification::Hash(BlockHash::from_byte_array(bytes)))
+    }
+    b"rawblock" => {
+      Ok(BlockNotification::Raw(consensus::deserialize(body).map_err(
+        |e| anyhow::anyhow!("Failed to deserialize notified block: {}", e)
+      )?))
+    }
+    topic => anyhow::bail!("unexpected ZMQ topic {}", String::from_utf8_lossy(topic)),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use bitcoin::{consensus, constants::genesis_block, Network};
+
+  use super::{parse_notification, BlockNotification};
+
+  fn sequence(number: u32) -> Vec<u8> {
+    number.to_le_bytes().to_vec()
+  }
+
+  #[test]
+  fn parses_hashblock() {
+    let block_hash = genesis_block(Network::Regtest).block_hash();
+    // display order, as bitcoind publishes it
+    let body = hex::decode(block_hash.to_string()).unwrap();
+
+    let notification = parse_notification(&[b"hashblock".to_vec(), body, sequence(7)]);
+    let Ok(BlockNotification::Hash(notified)) = notification else {
+      panic!("expected a block hash notification");
+    };
+    assert_eq!(notified, block_hash);
+  }
+
+  #[test]
+  fn parses_rawblock() {
+    let block = genesis_block(Network::Regtest);
+
+    let notification = parse_notification(&[b"rawblock".to_vec(), consensus::serialize(&block), sequence(0)]);
+    let Ok(BlockNotification::Raw(notified)) = notification else {
+      panic!("expected a raw block notification");
+    };
+    assert_eq!(notified.block_hash(), block.block_hash());
+  }
+
+  #[test]
+  fn rejects_wrong_number_of_parts() {
+    let body = vec![0; 32];
+    assert!(parse_notification(&[b"hashblock".to_vec(), body.clone()]).is_err());
+    assert!(parse_notification(&[b"hashblock".to_vec(), body, sequence(0), Vec::new()]).is_err());
+    assert!(parse_notification(&[]).is_err());
+  }
+
+  #[test]
+  fn rejects_unknown_topic() {
+    let notification = parse_notification(&[b"hashtx".to_vec(), vec![0; 32], sequence(0)]);
+    assert!(notification.is_err());
+  }
+
+  #[test]
+  fn rejects_malformed_hash() {
+    let notification = parse_notification(&[b"hashblock".to_vec(), vec![0; 31], sequence(0)]);
+    assert!(notification.is_err());
+  }
+}
@@ -0,0 +1,43 @@
+use bitcoin::{hashes::{sha256, Hash as _}, Address, Network, Script};
+
+// named after Esplora's `scriptpubkey_type`
+pub fn script_type(script: &Script) -> &'static str {
+  if script.is_p2pkh() {
+    "p2pkh"
+  } else if script.is_p2sh() {
+    "p2sh"
+  } else if script.is_p2wpkh() {
+    "v0_p2wpkh"
+  } else if script.is_p2wsh() {
+    "v0_p2wsh"
+  } else if script.is_p2tr() {
+    "v1_p2tr"
+  } else if script.is_op_return() {
+    "op_return"
+  } else if script.is_p2pk() {
+    "p2pk"
+  } else if script.is_multisig() {
+    "multisig"
+  } else if script.is_witness_program() {
+    "unknown_witness"
+  } else {
+    "unknown"
+  }
+}
+
+pub fn script_address(script: &Script, network: Network) -> Option<String> {
+  Address::from_script(script, network).ok().map(|address| address.to_string())
+}
+
+// Electrum protocol script hashes are the SHA256 of the script in reversed hex
+pub fn parse_electrum_script_hash(electrum_script_hash: &str) -> anyhow::Result<sha256::Hash> {
+  let mut bytes = hex::decode(electrum_script_hash)?;
+  bytes.reverse();
+  Ok(sha256::Hash::from_slice(&bytes)?)
+}
+
+pub fn electrum_script_hash(script: &Script) -> String {
+  let mut bytes = sha256::Hash::hash(script.as_bytes()).to_byte_array();
+  bytes.reverse();
+  hex::encode(bytes)
+}
@@ -0,0 +1,33 @@
+use std::ops::Range;
+
+use crate::store::{Batch, BlockHeight, Store};
+
+// Data dirs scanned before an index existed lack its entries for the blocks scanned so far. The scanner
+// fills them in from those blocks again, keeping track of the heights left to do under this key.
+const BLOCK_BACKFILL_KEY: &[u8] = b"block_backfill";
+
+impl Store {
+  pub fn get_block_backfill(&self) -> anyhow::Result<Option<Range<BlockHeight>>> {
+    let cf = self.db.cf_handle("metadata").unwrap();
+    let Some(value) = self.db.get_cf(&cf, BLOCK_BACKFILL_KEY)? else {
+      return Ok(None);
+    };
+    let (start, end) = value.split_at_checked(BlockHeight::BITS as usize / 8).ok_or_else(
+      || anyhow::anyhow!("invalid block backfill range of {} bytes", value.len())
+    )?;
+    Ok(Some(BlockHeight::from_be_bytes(start.try_into()?)..BlockHeight::from_be_bytes(end.try_into()?)))
+  }
+}
+
+impl Batch<'_> {
+  pub fn set_block_backfill(&mut self, heights: Option<Range<BlockHeight>>) {
+    let cf = self.store.db.cf_handle("metadata").unwrap();
+    match heights {
+      Some(heights) if !heights.is_empty() => {
+        let value = [heights.start.to_be_bytes(), heights.end.to_be_bytes()].concat();
+        self.batch.put_cf(&cf, BLOCK_BACKFILL_KEY, value);
+      }
+      _ => self.batch.delete_cf(&cf, BLOCK_BACKFILL_KEY),
+    }
+  }
+}
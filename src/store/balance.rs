@@ -0,0 +1,220 @@
+use std::collections::BTreeMap;
+
+use bitcoin::{hashes::Hash, Amount, ScriptHash, SignedAmount};
+use byten::{Decode, Encode, Measure, prelude::EncodeToVec, var};
+use rocksdb::SliceTransform;
+
+use crate::store::{codec::ScriptHashCodec, migration::Migration, txo::{LockerScriptHashAndOutpoint, TXOStoreRead as _}, Batch, BlockHeight, Seek, Store};
+
+// Confirmed balance of a locker script after each block changing it, so a historical balance is a single seek.
+// Values are u64 BE satoshis. Writers read the balance left by earlier blocks, the scanner writes one batch at a time.
+
+pub fn cf_descriptors(common_opts: &rocksdb::Options) -> Vec<rocksdb::ColumnFamilyDescriptor> {
+  let mut locker_script_hash_and_height_to_balance_opts = common_opts.clone();
+  locker_script_hash_and_height_to_balance_opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(ScriptHash::LEN));
+
+  vec![
+    rocksdb::ColumnFamilyDescriptor::new("locker_script_hash_and_height_to_balance", locker_script_hash_and_height_to_balance_opts),
+  ]
+}
+
+fn decode_balance(value: &[u8]) -> anyhow::Result<Amount> {
+  Ok(Amount::from_sat(u64::from_be_bytes(value.try_into()?)))
+}
+
+// heights are fixed-size big endian here, so balances of a script iterate in height order
+#[derive(Encode, Decode, Measure)]
+pub struct LockerScriptHashAndHeight {
+  #[byten(ScriptHashCodec)]
+  pub locker_script_hash: ScriptHash,
+  #[byten(var::U32BE)]
+  pub height: BlockHeight,
+}
+
+fn encode_key(locker_script_hash: &ScriptHash, height: BlockHeight) -> Vec<u8> {
+  LockerScriptHashAndHeight { locker_script_hash: *locker_script_hash, height }.encode_to_vec().unwrap()
+}
+
+pub trait BalanceStoreRead {
+  // heights where the script's TXOs cancel out, e.g. self-transfers, have no entry
+  fn get_balance_history<'store>(
+    &'store self,
+    locker_script_hash: &ScriptHash,
+    seek: Seek<BlockHeight>,
+  ) -> anyhow::Result<impl 'store + Iterator<Item = anyhow::Result<(BlockHeight, Amount)>>>;
+
+  fn get_balance_at(&self, locker_script_hash: &ScriptHash, height: BlockHeight) -> anyhow::Result<Amount>;
+}
+
+pub trait BalanceStoreWrite {
+  // heights must be above those of the balances stored so far, entries may repeat a script and height
+  fn add_balance_deltas<'data>(&mut self, entries: impl Iterator<Item = (&'data ScriptHash, BlockHeight, SignedAmount)>) -> anyhow::Result<()>;
+  fn remove_balances<'data>(&mut self, entries: impl Iterator<Item = (&'data ScriptHash, BlockHeight)>);
+}
+
+impl BalanceStoreRead for Store {
+  fn get_balance_history<'store>(
+    &'store self,
+    locker_script_hash: &ScriptHash,
+    seek: Seek<BlockHeight>,
+  ) -> anyhow::Result<impl 'store + Iterator<Item = anyhow::Result<(BlockHeight, Amount)>>> {
+    let cf = self.db.cf_handle("locker_script_hash_and_height_to_balance").unwrap();
+    let start = match seek {
+      Seek::First => encode_key(locker_script_hash, BlockHeight::MIN),
+      Seek::Last => encode_key(locker_script_hash, BlockHeight::MAX),
+      Seek::After(height) | Seek::Before(height) => encode_key(locker_script_hash, height),
+    };
+
+    let iter = self.db.iterator_cf(&cf, rocksdb::IteratorMode::From(&start, seek.direction()));
+    Ok(
+      iter.map(|res| -> anyhow::Result<_> {
+        let (key, value) = res?;
+        let key = LockerScriptHashAndHeight::decode(key.as_ref(), &mut 0)?;
+        Ok((key.locker_script_hash, key.height, decode_balance(value.as_ref())?))
+      })
+      .take_while({
+        let locker_script_hash = locker_script_hash.clone();
+        move |entry| {
+          match entry {
+            Ok((h, _, _)) => *h == locker_script_hash,
+            Err(_) => true,
+          }
+        }
+      })
+      .filter(move |entry| {
+        match entry {
+          Ok((_, height, _)) => seek.cursor() != Some(height),
+          Err(_) => true,
+        }
+      })
+      .map(|entry| entry.map(|(_, height, balance)| (height, balance)))
+    )
+  }
+
+  fn get_balance_at(&self, locker_script_hash: &ScriptHash, height: BlockHeight) -> anyhow::Result<Amount> {
+    let Some((_, balance)) = self.get_balance_history(locker_script_hash, Seek::Before(height.saturating_add(1)))?.next().transpose()? else {
+      return Ok(Amount::ZERO);
+    };
+    Ok(balance)
+  }
+}
+
+impl BalanceStoreWrite for Batch<'_> {
+  fn add_balance_deltas<'data>(&mut self, entries: impl Iterator<Item = (&'data ScriptHash, BlockHeight, SignedAmount)>) -> anyhow::Result<()> {
+    let store = self.store;
+    let cf = store.db.cf_handle("locker_script_hash_and_height_to_balance").unwrap();
+
+    // ordered by script, then height
+    let mut deltas = BTreeMap::<(&ScriptHash, BlockHeight), SignedAmount>::new();
+    for (locker_script_hash, height, delta) in entries {
+      *deltas.entry((locker_script_hash, height)).or_insert(SignedAmount::ZERO) += delta;
+    }
+
+    // one iterator seeks to the last balance of each script
+    let mut iter = store.db.raw_iterator_cf(&cf);
+    let mut balance = None::<(&ScriptHash, SignedAmount)>;
+    for ((locker_script_hash, height), delta) in deltas {
+      let previous = match balance {
+        Some((balance_script_hash, balance)) if balance_script_hash == locker_script_hash => balance,
+        _ => {
+          iter.seek_for_prev(encode_key(locker_script_hash, BlockHeight::MAX));
+          match iter.key() {
+            Some(key) if key.starts_with(locker_script_hash.as_byte_array()) => {
+              let previous_height = LockerScriptHashAndHeight::decode(key, &mut 0)?.height;
+              if previous_height >= height {
+                anyhow::bail!("balance of {} is already stored at height {}, not below {}", locker_script_hash, previous_height, height);
+              }
+              decode_balance(iter.value().unwrap())?.to_signed()?
+            }
+            _ => {
+              iter.status()?;
+              SignedAmount::ZERO
+            }
+          }
+        }
+      };
+      let updated = previous + delta;
+      if updated.is_negative() {
+        anyhow::bail!("balance of {} goes negative at height {}", locker_script_hash, height);
+      }
+      if delta != SignedAmount::ZERO {
+        self.batch.put_cf(&cf, encode_key(locker_script_hash, height), (updated.to_sat() as u64).to_be_bytes());
+      }
+      balance = Some((locker_script_hash, updated));
+    }
+    Ok(())
+  }
+
+  fn remove_balances<'data>(&mut self, entries: impl Iterator<Item = (&'data ScriptHash, BlockHeight)>) {
+    let cf = self.store.db.cf_handle("locker_script_hash_and_height_to_balance").unwrap();
+
+    for (locker_script_hash, height) in entries {
+      self.batch.delete_cf(&cf, encode_key(locker_script_hash, height));
+    }
+  }
+}
+
+impl Store {
+  // fills the balance index of a data dir created before it existed, a script at a time
+  pub(crate) fn index_balances(&self) -> anyhow::Result<()> {
+    let cf_locker_script_hash_and_outpoint = self.db.cf_handle("locker_script_hash_and_outpoint").unwrap();
+    let chunk_size = 100_000;
+
+    // entries of an interrupted run are written again, so the run needs no resume point
+    let mut batch = Batch {
+      store: self,
+      batch: rocksdb::WriteBatch::default(),
+    };
+    let mut locker_script_hash = None;
+    let mut outpoints = Vec::new();
+    for res in self.db.full_iterator_cf(&cf_locker_script_hash_and_outpoint, rocksdb::IteratorMode::Start) {
+      let (key, _) = res?;
+      let key = LockerScriptHashAndOutpoint::decode(key.as_ref(), &mut 0)?;
+      if locker_script_hash != Some(key.locker_script_hash) {
+        if let Some(locker_script_hash) = locker_script_hash {
+          batch.put_script_balances(&locker_script_hash, &outpoints)?;
+        }
+        locker_script_hash = Some(key.locker_script_hash);
+        outpoints.clear();
+      }
+      outpoints.push(key.outpoint);
+      if batch.batch.len() >= chunk_size {
+        self.db.write(std::mem::take(&mut batch.batch))?;
+      }
+    }
+    if let Some(locker_script_hash) = locker_script_hash {
+      batch.put_script_balances(&locker_script_hash, &outpoints)?;
+    }
+    batch.set_migration_pending(Migration::Balances, false);
+    batch.commit()
+  }
+}
+
+impl Batch<'_> {
+  // `outpoints` are all the TXOs of the script, sorted
+  fn put_script_balances(&mut self, locker_script_hash: &ScriptHash, outpoints: &[bitcoin::OutPoint]) -> anyhow::Result<()> {
+    let cf = self.store.db.cf_handle("locker_script_hash_and_height_to_balance").unwrap();
+
+    let mut deltas = BTreeMap::<BlockHeight, SignedAmount>::new();
+    for (outpoint, txo) in outpoints.iter().zip(self.store.get_txos(outpoints.iter())?) {
+      let Some(txo) = txo? else {
+        anyhow::bail!("missing txo {}", outpoint);
+      };
+      let value = txo.value.to_signed()?;
+      *deltas.entry(txo.generated_height).or_insert(SignedAmount::ZERO) += value;
+      if let Some(spent_height) = txo.spent_height {
+        *deltas.entry(spent_height).or_insert(SignedAmount::ZERO) -= value;
+      }
+    }
+
+    let mut balance = SignedAmount::ZERO;
+    for (height, delta) in deltas {
+      if delta == SignedAmount::ZERO {
+        continue;
+      }
+      balance += delta;
+      self.batch.put_cf(&cf, encode_key(locker_script_hash, height), (balance.to_sat() as u64).to_be_bytes());
+    }
+    Ok(())
+  }
+}
@@ -1,15 +1,34 @@
+use std::collections::VecDeque;
+
 use bitcoin::{hashes::Hash, BlockHash};
 
 use crate::store::{Store, Batch};
 
 use super::BlockHeight;
 
+// bitcoind's `mediantime` of a block is the median of its own and the 10 preceding header times
+const MEDIAN_TIME_SPAN: usize = 11;
+
+#[derive(Clone, Copy, Debug)]
+pub struct BlockTime {
+  // header timestamp, miners may set it out of order
+  pub time: u32,
+  // never decreases with height
+  pub median_time_past: u32,
+}
+
 pub trait BlockStoreRead {
   fn get_tip_block(&self) -> anyhow::Result<Option<(BlockHeight, BlockHash)>>;
+  fn get_block_hash(&self, height: BlockHeight) -> anyhow::Result<Option<BlockHash>>;
+  fn get_block_time(&self, height: BlockHeight) -> anyhow::Result<Option<BlockTime>>;
+  // the highest block whose median time past is at or before `time`
+  fn get_height_at_time(&self, time: u32) -> anyhow::Result<Option<BlockHeight>>;
 }
 
 pub trait BlockStoreWrite {
   fn insert_blocks<'a>(&mut self, entries: impl Iterator<Item = (&'a BlockHash, BlockHeight)>);
+  fn remove_blocks<'a>(&mut self, entries: impl Iterator<Item = (&'a BlockHash, BlockHeight)>);
+  fn insert_block_times<'a>(&mut self, entries: impl Iterator<Item = (BlockHeight, &'a BlockTime)>);
 }
 
 impl BlockStoreRead for Store {
@@ -23,6 +42,81 @@ impl BlockStoreRead for Store {
       BlockHash::from_byte_array(value.as_ref().try_into()?),
     )))
   }
+
+  fn get_block_hash(&self, height: BlockHeight) -> anyhow::Result<Option<BlockHash>> {
+    let cf = self.db.cf_handle("height_to_block_hash").unwrap();
+    let Some(value) = self.db.get_cf(&cf, height.to_be_bytes())? else {
+      return Ok(None);
+    };
+    Ok(Some(BlockHash::from_byte_array(value.as_slice().try_into()?)))
+  }
+
+  fn get_block_time(&self, height: BlockHeight) -> anyhow::Result<Option<BlockTime>> {
+    let cf = self.db.cf_handle("height_to_block_time").unwrap();
+    let Some(value) = self.db.get_cf(&cf, height.to_be_bytes())? else {
+      return Ok(None);
+    };
+    let (time, median_time_past) = value.split_at_checked(4).ok_or_else(
+      || anyhow::anyhow!("invalid block time of {} bytes", value.len())
+    )?;
+    Ok(Some(BlockTime {
+      time: u32::from_be_bytes(time.try_into()?),
+      median_time_past: u32::from_be_bytes(median_time_past.try_into()?),
+    }))
+  }
+
+  fn get_height_at_time(&self, time: u32) -> anyhow::Result<Option<BlockHeight>> {
+    let Some((tip_height, _)) = self.get_tip_block()? else {
+      return Ok(None);
+    };
+    let median_time_past = |height: BlockHeight| -> anyhow::Result<u32> {
+      let Some(block_time) = self.get_block_time(height)? else {
+        anyhow::bail!("missing block time at height {}", height);
+      };
+      Ok(block_time.median_time_past)
+    };
+
+    // binary search relies on median time past never decreasing
+    if median_time_past(0)? > time {
+      return Ok(None);
+    }
+    let (mut low, mut high) = (0, tip_height);
+    while low < high {
+      let mid = low + (high - low).div_ceil(2);
+      if median_time_past(mid)? <= time {
+        low = mid;
+      } else {
+        high = mid - 1;
+      }
+    }
+    Ok(Some(low))
+  }
+}
+
+impl Store {
+  // times of the consecutive blocks starting at `start_height`, given their header times
+  pub fn derive_block_times(&self, start_height: BlockHeight, times: impl Iterator<Item = u32>) -> anyhow::Result<Vec<BlockTime>> {
+    let mut window = VecDeque::with_capacity(MEDIAN_TIME_SPAN);
+    for height in start_height.saturating_sub(MEDIAN_TIME_SPAN as BlockHeight - 1)..start_height {
+      let Some(block_time) = self.get_block_time(height)? else {
+        anyhow::bail!("missing block time at height {}", height);
+      };
+      window.push_back(block_time.time);
+    }
+
+    Ok(times.map(|time| {
+      if window.len() == MEDIAN_TIME_SPAN {
+        window.pop_front();
+      }
+      window.push_back(time);
+      let mut sorted = window.iter().copied().collect::<Vec<_>>();
+      sorted.sort_unstable();
+      BlockTime {
+        time,
+        median_time_past: sorted[sorted.len() / 2],
+      }
+    }).collect())
+  }
 }
 
 impl BlockStoreWrite for Batch<'_> {
@@ -35,11 +129,33 @@ impl BlockStoreWrite for Batch<'_> {
       self.batch.put_cf(&cf_height_to_hash, height.to_be_bytes(), hash.as_byte_array());
     }
   }
+
+  fn remove_blocks<'a>(&mut self, entries: impl Iterator<Item = (&'a BlockHash, BlockHeight)>) {
+    let cf_hash_to_height = self.store.db.cf_handle("block_hash_to_height").unwrap();
+    let cf_height_to_hash = self.store.db.cf_handle("height_to_block_hash").unwrap();
+    let cf_height_to_time = self.store.db.cf_handle("height_to_block_time").unwrap();
+
+    for (hash, height) in entries {
+      self.batch.delete_cf(&cf_hash_to_height, hash.as_byte_array());
+      self.batch.delete_cf(&cf_height_to_hash, height.to_be_bytes());
+      self.batch.delete_cf(&cf_height_to_time, height.to_be_bytes());
+    }
+  }
+
+  fn insert_block_times<'a>(&mut self, entries: impl Iterator<Item = (BlockHeight, &'a BlockTime)>) {
+    let cf = self.store.db.cf_handle("height_to_block_time").unwrap();
+
+    for (height, block_time) in entries {
+      let value = [block_time.time.to_be_bytes(), block_time.median_time_past.to_be_bytes()].concat();
+      self.batch.put_cf(&cf, height.to_be_bytes(), value);
+    }
+  }
 }
 
 pub fn cf_descriptors(common_opts: &rocksdb::Options) -> Vec<rocksdb::ColumnFamilyDescriptor> {
   vec![
     rocksdb::ColumnFamilyDescriptor::new("block_hash_to_height", common_opts.clone()),
     rocksdb::ColumnFamilyDescriptor::new("height_to_block_hash", common_opts.clone()),
+    rocksdb::ColumnFamilyDescriptor::new("height_to_block_time", common_opts.clone()),
   ]
 }
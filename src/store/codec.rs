@@ -3,6 +3,8 @@ use std::result::Result;
 use bitcoin::{hashes::Hash, Amount, OutPoint, ScriptHash, Txid};
 use byten::{Decode, DecodeError, Decoder, Encode, EncodeError, Encoder, FixedMeasurer, Measurer, prim::U64BE, var};
 
+use crate::store::txo::TXOSpender;
+
 pub struct ScriptHashCodec;
 
 impl Decoder for ScriptHashCodec {
@@ -154,3 +156,45 @@ impl FixedMeasurer for OutPointCodec {
     }
   }
 }
+
+// TXO states written before spenders were recorded end without this field, so it decodes as None
+pub struct SpenderCodec;
+
+impl Decoder for SpenderCodec {
+  type Decoded = Option<TXOSpender>;
+
+  fn decode(&self, encoded: &[u8], offset: &mut usize) -> Result<Self::Decoded, DecodeError> {
+    if *offset == encoded.len() {
+      return Ok(None);
+    }
+    let [tag] = <[u8; 1]>::decode(encoded, offset)?;
+    if tag == 0 {
+      return Ok(None);
+    }
+    let txid = TxidCodec.decode(encoded, offset)?;
+    let vin = var::U32BE.decode(encoded, offset)?;
+    Ok(Some(TXOSpender { txid, vin }))
+  }
+}
+
+impl Encoder for SpenderCodec {
+  type Decoded = Option<TXOSpender>;
+  fn encode(&self, decoded: &Self::Decoded, encoded: &mut [u8], offset: &mut usize) -> Result<(), EncodeError> {
+    let Some(spender) = decoded else {
+      return Encode::encode(&[0u8], encoded, offset);
+    };
+    Encode::encode(&[1u8], encoded, offset)?;
+    TxidCodec.encode(&spender.txid, encoded, offset)?;
+    var::U32BE.encode(&spender.vin, encoded, offset)
+  }
+}
+
+impl Measurer for SpenderCodec {
+  type Decoded = Option<TXOSpender>;
+  fn measure(&self, decoded: &Self::Decoded) -> usize {
+    match decoded {
+      None => 1,
+      Some(spender) => 1 + Txid::LEN + var::U32BE.measure(&spender.vin),
+    }
+  }
+}
@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+
+use bitcoin::{hashes::Hash, ScriptHash, SignedAmount, Txid};
+use byten::{Decode, Encode, Measure, prelude::EncodeToVec, var};
+use rocksdb::SliceTransform;
+
+use crate::store::{codec::ScriptHashCodec, tx::TXLocation, Batch, BlockHeight, Seek, Store};
+
+// Transactions generating or spending TXOs of a locker script in block order, with their net effect on it.
+// Blocks scanned before the index existed get their entries from the block backfill.
+
+pub fn cf_descriptors(common_opts: &rocksdb::Options) -> Vec<rocksdb::ColumnFamilyDescriptor> {
+  let mut locker_script_hash_and_tx_location_opts = common_opts.clone();
+  locker_script_hash_and_tx_location_opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(ScriptHash::LEN));
+
+  vec![
+    rocksdb::ColumnFamilyDescriptor::new("locker_script_hash_and_tx_location", locker_script_hash_and_tx_location_opts),
+  ]
+}
+
+// locations are fixed-size big endian here, so transactions of a script iterate in block order
+#[derive(Encode, Decode, Measure)]
+pub struct LockerScriptHashAndTXLocation {
+  #[byten(ScriptHashCodec)]
+  pub locker_script_hash: ScriptHash,
+  #[byten(var::U32BE)]
+  pub height: BlockHeight,
+  #[byten(var::U32BE)]
+  pub position: u32,
+}
+
+fn encode_key(locker_script_hash: &ScriptHash, location: TXLocation) -> Vec<u8> {
+  LockerScriptHashAndTXLocation {
+    locker_script_hash: *locker_script_hash,
+    height: location.height,
+    position: location.position,
+  }.encode_to_vec().unwrap()
+}
+
+fn encode_value(txid: &Txid, delta: SignedAmount) -> Vec<u8> {
+  [txid.as_byte_array().as_slice(), &delta.to_sat().to_be_bytes()].concat()
+}
+
+fn decode_value(value: &[u8]) -> anyhow::Result<(Txid, SignedAmount)> {
+  let (txid, delta) = value.split_at_checked(Txid::LEN).ok_or_else(
+    || anyhow::anyhow!("invalid script transaction value of {} bytes", value.len())
+  )?;
+  Ok((Txid::from_byte_array(txid.try_into()?), SignedAmount::from_sat(i64::from_be_bytes(delta.try_into()?))))
+}
+
+pub trait HistoryStoreRead {
+  // fails while the block backfill has not filled in the blocks scanned before the index existed
+  fn get_locker_script_transactions<'store>(
+    &'store self,
+    locker_script_hash: &ScriptHash,
+    seek: Seek<TXLocation>,
+  ) -> anyhow::Result<impl 'store + Iterator<Item = anyhow::Result<(TXLocation, Txid, SignedAmount)>>>;
+}
+
+pub trait HistoryStoreWrite {
+  // entries may repeat a script and transaction, their deltas add up
+  fn add_script_transactions<'data>(&mut self, entries: impl Iterator<Item = (&'data ScriptHash, TXLocation, &'data Txid, SignedAmount)>);
+  fn remove_script_transactions<'data>(&mut self, entries: impl Iterator<Item = (&'data ScriptHash, TXLocation)>);
+}
+
+impl HistoryStoreRead for Store {
+  fn get_locker_script_transactions<'store>(
+    &'store self,
+    locker_script_hash: &ScriptHash,
+    seek: Seek<TXLocation>,
+  ) -> anyhow::Result<impl 'store + Iterator<Item = anyhow::Result<(TXLocation, Txid, SignedAmount)>>> {
+    if self.get_block_backfill()?.is_some() {
+      anyhow::bail!("transaction history is incomplete until the block backfill is done");
+    }
+
+    let cf = self.db.cf_handle("locker_script_hash_and_tx_location").unwrap();
+    let start = match seek {
+      Seek::First => encode_key(locker_script_hash, TXLocation { height: BlockHeight::MIN, position: 0 }),
+      Seek::Last => encode_key(locker_script_hash, TXLocation { height: BlockHeight::MAX, position: u32::MAX }),
+      Seek::After(location) | Seek::Before(location) => encode_key(locker_script_hash, location),
+    };
+
+    let iter = self.db.iterator_cf(&cf, rocksdb::IteratorMode::From(&start, seek.direction()));
+    Ok(
+      iter.map(|res| -> anyhow::Result<_> {
+        let (key, value) = res?;
+        let key = LockerScriptHashAndTXLocation::decode(key.as_ref(), &mut 0)?;
+        let (txid, delta) = decode_value(value.as_ref())?;
+        Ok((key.locker_script_hash, TXLocation { height: key.height, position: key.position }, txid, delta))
+      })
+      .take_while({
+        let locker_script_hash = locker_script_hash.clone();
+        move |entry| {
+          match entry {
+            Ok((h, _, _, _)) => *h == locker_script_hash,
+            Err(_) => true,
+          }
+        }
+      })
+      .filter(move |entry| {
+        match entry {
+          Ok((_, location, _, _)) => seek.cursor() != Some(location),
+          Err(_) => true,
+        }
+      })
+      .map(|entry| entry.map(|(_, location, txid, delta)| (location, txid, delta)))
+    )
+  }
+}
+
+impl HistoryStoreWrite for Batch<'_> {
+  fn add_script_transactions<'data>(&mut self, entries: impl Iterator<Item = (&'data ScriptHash, TXLocation, &'data Txid, SignedAmount)>) {
+    let cf = self.store.db.cf_handle("locker_script_hash_and_tx_location").unwrap();
+
+    let mut transactions = BTreeMap::<(&ScriptHash, TXLocation), (&Txid, SignedAmount)>::new();
+    for (locker_script_hash, location, txid, delta) in entries {
+      transactions.entry((locker_script_hash, location)).or_insert((txid, SignedAmount::ZERO)).1 += delta;
+    }
+
+    // self-transfers with no net effect are transactions of the script all the same
+    for ((locker_script_hash, location), (txid, delta)) in transactions {
+      self.batch.put_cf(&cf, encode_key(locker_script_hash, location), encode_value(txid, delta));
+    }
+  }
+
+  fn remove_script_transactions<'data>(&mut self, entries: impl Iterator<Item = (&'data ScriptHash, TXLocation)>) {
+    let cf = self.store.db.cf_handle("locker_script_hash_and_tx_location").unwrap();
+
+    for (locker_script_hash, location) in entries {
+      self.batch.delete_cf(&cf, encode_key(locker_script_hash, location));
+    }
+  }
+}
@@ -0,0 +1,136 @@
+use crate::store::{block::BlockStoreRead as _, Batch, Store};
+
+// Indexes added after a data dir was created are filled in from what it holds. Their migration is marked
+// pending in the metadata before their column families get created, and the mark is only cleared by the
+// write finishing the migration, so an interrupted one runs again on the next open.
+#[derive(Clone, Copy, Debug)]
+pub enum Migration {
+  UnspentOutpoints,
+  Balances,
+  // indexes filled in from the blocks by the scanner
+  BlockBackfill,
+}
+
+impl Migration {
+  const ALL: [Migration; 3] = [Migration::UnspentOutpoints, Migration::Balances, Migration::BlockBackfill];
+
+  fn key(&self) -> &'static [u8] {
+    match self {
+      Migration::UnspentOutpoints => b"pending_migration/unspent_outpoints",
+      Migration::Balances => b"pending_migration/balances",
+      Migration::BlockBackfill => b"pending_migration/block_backfill",
+    }
+  }
+
+  fn cfs(&self) -> &'static [&'static str] {
+    match self {
+      Migration::UnspentOutpoints => &["locker_script_hash_and_unspent_outpoint"],
+      Migration::Balances => &["locker_script_hash_and_height_to_balance"],
+      Migration::BlockBackfill => &["spending_txid_and_vin", "txid_to_location", "height_to_block_time", "script_hash_to_script", "script_sha256_to_script_hash", "locker_script_hash_and_tx_location"],
+    }
+  }
+
+  // migrations of an existing data dir lacking some of their column families
+  pub fn missing(existing_cfs: &[String]) -> Vec<Migration> {
+    if existing_cfs.is_empty() {
+      return Vec::new();
+    }
+    Self::ALL.into_iter()
+      .filter(|migration| migration.cfs().iter().any(|cf| !existing_cfs.iter().any(|name| name == cf)))
+      .collect()
+  }
+}
+
+pub fn cf_descriptors(common_opts: &rocksdb::Options) -> Vec<rocksdb::ColumnFamilyDescriptor> {
+  vec![
+    rocksdb::ColumnFamilyDescriptor::new("metadata", common_opts.clone()),
+  ]
+}
+
+impl Store {
+  fn is_migration_pending(&self, migration: Migration) -> anyhow::Result<bool> {
+    let cf = self.db.cf_handle("metadata").unwrap();
+    Ok(self.db.get_cf(&cf, migration.key())?.is_some())
+  }
+
+  pub(crate) fn run_pending_migrations(&self) -> anyhow::Result<()> {
+    if self.is_migration_pending(Migration::UnspentOutpoints)? {
+      println!("Indexing unspent outpoints of existing data dir");
+      self.index_unspent_outpoints()?;
+    }
+
+    if self.is_migration_pending(Migration::Balances)? {
+      println!("Indexing balances of existing data dir");
+      self.index_balances()?;
+    }
+
+    if self.is_migration_pending(Migration::BlockBackfill)? {
+      let mut batch = Batch {
+        store: self,
+        batch: rocksdb::WriteBatch::default(),
+      };
+      if let Some((tip_height, _)) = self.get_tip_block()? {
+        println!("Indexes of existing data dir will be backfilled up to height {}", tip_height);
+        batch.set_block_backfill(Some(0..tip_height + 1));
+      }
+      batch.set_migration_pending(Migration::BlockBackfill, false);
+      batch.commit()?;
+    }
+
+    Ok(())
+  }
+}
+
+impl Batch<'_> {
+  pub fn set_migration_pending(&mut self, migration: Migration, pending: bool) {
+    let cf = self.store.db.cf_handle("metadata").unwrap();
+    if pending {
+      self.batch.put_cf(&cf, migration.key(), &[]);
+    } else {
+      self.batch.delete_cf(&cf, migration.key());
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::Migration;
+  use crate::{store::Batch, test_util::{coinbase, other_script, outpoint, script, tx, TestStore}};
+
+  #[test]
+  fn migrations_index_like_the_scanner() {
+    let mut test_store = TestStore::open("migrations");
+    let coinbase0 = coinbase(0, &[(script(), 50)]);
+    test_store.connect_block(vec![coinbase0.clone()]);
+    let funding = tx(&[outpoint(&coinbase0, 0)], &[(script(), 20), (other_script(), 30)]);
+    test_store.connect_block(vec![coinbase(1, &[(other_script(), 50)]), funding.clone()]);
+    let spending = tx(&[outpoint(&funding, 0)], &[(other_script(), 20)]);
+    test_store.connect_block(vec![coinbase(2, &[(script(), 10)]), spending]);
+
+    let cfs = ["locker_script_hash_and_unspent_outpoint", "locker_script_hash_and_height_to_balance"];
+    let scanned = cfs.map(|cf| test_store.store().cf_entries(cf));
+    assert!(scanned.iter().all(|entries| !entries.is_empty()));
+
+    // as left by a version without the indexes
+    let store = test_store.store();
+    let mut batch = Batch {
+      store,
+      batch: rocksdb::WriteBatch::default(),
+    };
+    for name in cfs {
+      let cf = store.db.cf_handle(name).unwrap();
+      for (key, _) in store.cf_entries(name) {
+        batch.batch.delete_cf(&cf, key);
+      }
+    }
+    batch.set_migration_pending(Migration::UnspentOutpoints, true);
+    batch.set_migration_pending(Migration::Balances, true);
+    batch.commit().unwrap();
+
+    test_store.reopen();
+    let store = test_store.store();
+    assert_eq!(cfs.map(|cf| store.cf_entries(cf)), scanned);
+    assert!(!store.is_migration_pending(Migration::UnspentOutpoints).unwrap());
+    assert!(!store.is_migration_pending(Migration::Balances).unwrap());
+  }
+}
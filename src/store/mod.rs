@@ -1,12 +1,46 @@
 use std::iter;
 use rocksdb::WaitForCompactOptions;
 
+use crate::store::migration::Migration;
+
 pub mod block;
 pub mod txo;
+pub mod tx;
+pub mod balance;
+pub mod history;
+pub mod script;
 pub mod codec;
+pub mod rewind;
+pub mod backfill;
+pub mod migration;
 
 pub type BlockHeight = u32;
 
+// where an ordered index scan starts, cursors themselves are excluded
+#[derive(Clone, Copy)]
+pub enum Seek<K> {
+  First,
+  Last,
+  After(K),
+  Before(K),
+}
+
+impl<K> Seek<K> {
+  pub fn direction(&self) -> rocksdb::Direction {
+    match self {
+      Seek::First | Seek::After(_) => rocksdb::Direction::Forward,
+      Seek::Last | Seek::Before(_) => rocksdb::Direction::Reverse,
+    }
+  }
+
+  pub fn cursor(&self) -> Option<&K> {
+    match self {
+      Seek::First | Seek::Last => None,
+      Seek::After(cursor) | Seek::Before(cursor) => Some(cursor),
+    }
+  }
+}
+
 pub struct Store {
   pub(self) db: rocksdb::DB,
 }
@@ -31,22 +65,58 @@ impl Store {
 
     opts.set_block_based_table_factory(&block_opts);
 
-    let db = rocksdb::DB::open_cf_descriptors(
-      &opts,
-      path,
-      iter::empty().chain(
-        block::cf_descriptors(&opts),
-      ).chain(
-        txo::cf_descriptors(&opts),
-      ),
-    )?;
+    let existing_cfs = rocksdb::DB::list_cf(&opts, path).unwrap_or_default();
+    let cf_descriptors = || iter::empty().chain(
+      block::cf_descriptors(&opts),
+    ).chain(
+      txo::cf_descriptors(&opts),
+    ).chain(
+      tx::cf_descriptors(&opts),
+    ).chain(
+      balance::cf_descriptors(&opts),
+    ).chain(
+      history::cf_descriptors(&opts),
+    ).chain(
+      script::cf_descriptors(&opts),
+    ).chain(
+      migration::cf_descriptors(&opts),
+    );
+
+    // marked before the column families are created, opening only those the data dir has
+    let missing_migrations = Migration::missing(&existing_cfs);
+    if !missing_migrations.is_empty() {
+      let db = rocksdb::DB::open_cf_descriptors(
+        &opts,
+        path,
+        cf_descriptors().filter(|descriptor| {
+          descriptor.name() == "metadata" || existing_cfs.iter().any(|name| name == descriptor.name())
+        }),
+      )?;
+      let store = Self {
+        db,
+      };
+      let mut batch = Batch {
+        store: &store,
+        batch: rocksdb::WriteBatch::default(),
+      };
+      for migration in missing_migrations {
+        batch.set_migration_pending(migration, true);
+      }
+      batch.commit()?;
+    }
+
+    let db = rocksdb::DB::open_cf_descriptors(&opts, path, cf_descriptors())?;
 
     db.compact_range::<Vec<u8>, Vec<u8>>(None, None);
     db.wait_for_compact(&WaitForCompactOptions::default())?;
 
-    Ok(Self {
+    let store = Self {
       db,
-    })
+    };
+
+    store.run_pending_migrations()?;
+
+    Ok(store)
   }
 }
 
@@ -61,3 +131,16 @@ impl <'a> Batch<'a> {
     Ok(())
   }
 }
+
+#[cfg(test)]
+impl Store {
+  pub fn cf_names(&self) -> Vec<String> {
+    rocksdb::DB::list_cf(&rocksdb::Options::default(), self.db.path()).unwrap()
+  }
+
+  // every entry of a column family, merge operands resolved
+  pub fn cf_entries(&self, name: &str) -> Vec<(Box<[u8]>, Box<[u8]>)> {
+    let cf = self.db.cf_handle(name).unwrap();
+    self.db.iterator_cf(&cf, rocksdb::IteratorMode::Start).collect::<Result<Vec<_>, _>>().unwrap()
+  }
+}
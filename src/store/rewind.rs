@@ -0,0 +1,256 @@
+use std::{collections::HashMap, fmt};
+
+use bitcoin::{BlockHash, OutPoint, ScriptHash, Txid};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator as _};
+use tracing::instrument;
+
+use crate::store::{balance::BalanceStoreWrite as _, block::{BlockStoreRead as _, BlockStoreWrite as _}, history::HistoryStoreWrite as _, tx::{TXLocation, TXStoreRead as _, TXStoreWrite as _}, txo::{TXOGenerated, TXOState, TXOStoreRead as _, TXOStoreWrite}, Batch, BlockHeight, Store};
+
+#[derive(Clone, Copy)]
+pub struct RewoundBlock {
+  pub height: BlockHeight,
+  pub block_hash: BlockHash,
+  pub num_generated_txos: usize,
+  pub num_spent_txos: usize,
+}
+
+impl fmt::Display for RewoundBlock {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "block {} at height {} ({} generated and {} spent TXOs reverted)",
+      self.block_hash,
+      self.height,
+      self.num_generated_txos,
+      self.num_spent_txos,
+    )
+  }
+}
+
+// what a rewind undid, as it was before being undone
+pub struct Rewound {
+  // descending height
+  pub blocks: Vec<RewoundBlock>,
+  pub generated_txos: Vec<(OutPoint, TXOGenerated)>,
+  pub spent_txos: Vec<(OutPoint, TXOState)>,
+}
+
+impl Store {
+  // undoes every block above `target_height` in a single write batch
+  pub fn rewind_to(&self, target_height: BlockHeight) -> anyhow::Result<Rewound> {
+    let rewind = Rewind::build(self, target_height)?;
+
+    let mut batch = Batch {
+      store: self,
+      batch: rocksdb::WriteBatch::default(),
+    };
+    tracing::trace_span!("write").in_scope(|| rewind.write(&mut batch))?;
+    tracing::trace_span!("commit").in_scope(|| batch.commit())?;
+
+    let mut blocks = rewind.blocks;
+    blocks.reverse();
+    Ok(Rewound {
+      blocks,
+      generated_txos: rewind.generated_txos,
+      spent_txos: rewind.spent_txos,
+    })
+  }
+}
+
+struct Rewind {
+  target_height: BlockHeight,
+  tip_height: Option<BlockHeight>,
+  blocks: Vec<RewoundBlock>,
+  txids: Vec<Txid>,
+
+  generated_txos: Vec<(OutPoint, TXOGenerated)>,
+  spent_txos: Vec<(OutPoint, TXOState)>,
+}
+
+impl Rewind {
+  #[instrument(name = "Rewind::build", level="trace", skip_all, fields(
+    target_height = target_height,
+    num_blocks = tracing::field::Empty,
+    num_generated_txos = tracing::field::Empty,
+    num_spent_txos = tracing::field::Empty,
+  ))]
+  fn build(
+    store: &Store,
+    target_height: BlockHeight,
+  ) -> anyhow::Result<Self> {
+    let tip_height = store.get_tip_block()?.map(|(height, _)| height);
+
+    let mut rewind = Rewind {
+      target_height,
+      tip_height,
+      blocks: Vec::new(),
+      txids: Vec::new(),
+      generated_txos: Vec::new(),
+      spent_txos: Vec::new(),
+    };
+
+    let Some(tip_height) = tip_height else {
+      return Ok(rewind);
+    };
+
+    for height in (target_height + 1)..=tip_height {
+      rewind.scan_height(store, height)?;
+    }
+
+    tracing::Span::current().record("num_blocks", rewind.blocks.len());
+    tracing::Span::current().record("num_generated_txos", rewind.generated_txos.len());
+    tracing::Span::current().record("num_spent_txos", rewind.spent_txos.len());
+
+    Ok(rewind)
+  }
+
+  fn scan_height(
+    &mut self,
+    store: &Store,
+    height: BlockHeight,
+  ) -> anyhow::Result<()> {
+    let Some(block_hash) = store.get_block_hash(height)? else {
+      anyhow::bail!("missing block hash at height {}", height);
+    };
+
+    let num_generated_txos = self.generated_txos.len();
+    let generated_outpoints = store.get_generated_outpoints(height)?.collect::<anyhow::Result<Vec<_>>>()?;
+    let generated_txos = store.get_txos(generated_outpoints.iter())?;
+    for (outpoint, txo) in generated_outpoints.iter().zip(generated_txos) {
+      let Some(txo) = txo? else {
+        anyhow::bail!("missing txo {} generated at height {}", outpoint, height);
+      };
+      if txo.generated_height != height {
+        // duplicate coinbase txid, the TXO state belongs to the earlier block
+        tracing::warn!("skipping rewind of duplicate coinbase outpoint {} at height {}", outpoint, height);
+        continue;
+      }
+      // every transaction has an output at index 0
+      if outpoint.vout == 0 {
+        self.txids.push(outpoint.txid);
+      }
+      self.generated_txos.push((*outpoint, TXOGenerated {
+        locker_script_hash: txo.locker_script_hash,
+        value: txo.value,
+        generated_height: txo.generated_height,
+      }));
+    }
+
+    let num_spent_txos = self.spent_txos.len();
+    let spent_outpoints = store.get_spent_outpoints(height)?.collect::<anyhow::Result<Vec<_>>>()?;
+    let spent_txos = store.get_txos(spent_outpoints.iter())?;
+    for (outpoint, txo) in spent_outpoints.iter().zip(spent_txos) {
+      let Some(txo) = txo? else {
+        anyhow::bail!("missing txo {} spent at height {}", outpoint, height);
+      };
+      if txo.spent_height != Some(height) {
+        anyhow::bail!("txo {} is indexed as spent at height {} but its state disagrees", outpoint, height);
+      }
+      self.spent_txos.push((*outpoint, txo));
+    }
+
+    self.blocks.push(RewoundBlock {
+      height,
+      block_hash,
+      num_generated_txos: self.generated_txos.len() - num_generated_txos,
+      num_spent_txos: self.spent_txos.len() - num_spent_txos,
+    });
+
+    Ok(())
+  }
+
+  // blocks the backfill has not reached have neither spenders nor locations, nor script transactions to remove
+  fn script_transactions(&self, store: &Store) -> anyhow::Result<Vec<(ScriptHash, TXLocation)>> {
+    let mut locations = HashMap::new();
+    let mut script_transactions = Vec::new();
+    let txids = self.generated_txos.iter()
+      .map(|(outpoint, txo)| (txo.locker_script_hash, outpoint.txid))
+      .chain(self.spent_txos.iter().filter_map(|(_, txo)| Some((txo.locker_script_hash, txo.spender.as_ref()?.txid))));
+    for (locker_script_hash, txid) in txids {
+      let location = match locations.get(&txid) {
+        Some(location) => *location,
+        None => {
+          let location = store.get_tx_location(&txid)?;
+          locations.insert(txid, location);
+          location
+        }
+      };
+      if let Some(location) = location {
+        script_transactions.push((locker_script_hash, location));
+      }
+    }
+    Ok(script_transactions)
+  }
+
+  fn write(&self, store: &mut Batch) -> anyhow::Result<()> {
+    let tip_height = store.store.get_tip_block()?.map(|(height, _)| height);
+    if tip_height != self.tip_height {
+      anyhow::bail!(
+        "Store tip height {:?} changed since rewind to {} was built from tip height {:?}",
+        tip_height,
+        self.target_height,
+        self.tip_height,
+      );
+    }
+
+    // spends are reverted first, so TXOs generated above the target end up deleted
+    store.revert_spent_txos(self.spent_txos.par_iter().map(|(outpoint, txo)| (outpoint, txo)));
+
+    store.revert_generated_txos(self.generated_txos.par_iter().map(|(outpoint, txo)| (outpoint, txo)));
+
+    // balances below the target are left as they were, so those of rewound blocks just go away
+    store.remove_balances(
+      self.generated_txos.iter()
+        .map(|(_, txo)| (&txo.locker_script_hash, txo.generated_height))
+        .chain(self.spent_txos.iter().filter_map(|(_, txo)| Some((&txo.locker_script_hash, txo.spent_height?))))
+    );
+
+    let script_transactions = self.script_transactions(store.store)?;
+    store.remove_script_transactions(script_transactions.iter().map(|(locker_script_hash, location)| (locker_script_hash, *location)));
+
+    store.remove_txs(self.txids.iter());
+
+    store.remove_blocks(self.blocks.iter().map(|block| (&block.block_hash, block.height)));
+
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::BTreeMap;
+
+  use crate::{store::{block::BlockStoreRead as _, Store}, test_util::{coinbase, other_script, outpoint, script, tx, TestStore}};
+
+  // every column family's entries, merge operands resolved, but for the scripts which outlive the TXOs they lock
+  fn snapshot(store: &Store) -> BTreeMap<String, Vec<(Box<[u8]>, Box<[u8]>)>> {
+    store.cf_names().into_iter()
+      .filter(|name| name != "script_hash_to_script" && name != "script_sha256_to_script_hash")
+      .map(|name| {
+        let entries = store.cf_entries(&name);
+        (name, entries)
+      })
+      .collect()
+  }
+
+  #[test]
+  fn rewind_restores_every_index() {
+    let test_store = TestStore::open("rewind");
+    let coinbase0 = coinbase(0, &[(script(), 50)]);
+    let connected0 = test_store.connect_block(vec![coinbase0.clone()]);
+    let store = test_store.store();
+    let before = snapshot(store);
+
+    let funding = tx(&[outpoint(&coinbase0, 0)], &[(script(), 20), (other_script(), 30)]);
+    test_store.connect_block(vec![coinbase(1, &[(other_script(), 50)]), funding.clone()]);
+    // generated and spent within the rewound blocks
+    let spending = tx(&[outpoint(&funding, 0)], &[(other_script(), 20)]);
+    test_store.connect_block(vec![coinbase(2, &[(script(), 10)]), spending]);
+    assert_ne!(snapshot(store), before);
+
+    let rewound = store.rewind_to(0).unwrap();
+    assert_eq!(rewound.blocks.iter().map(|block| block.height).collect::<Vec<_>>(), vec![2, 1]);
+    assert_eq!(store.get_tip_block().unwrap(), Some(connected0.blocks[0]));
+    assert_eq!(snapshot(store), before);
+  }
+}
@@ -0,0 +1,80 @@
+use bitcoin::{hashes::{sha256, Hash as _}, ScriptBuf, ScriptHash};
+use byten::{prelude::EncoderToVec as _, Decoder as _};
+
+use crate::store::{Batch, Store, codec::ScriptHashCodec};
+
+// Scripts are looked up by the hash every other index keys them by. Entries are never removed on
+// rewind, since the hash pins the script down regardless of which blocks use it.
+// Electrum clients key scripts by their SHA256 instead, which is mapped to the script hash.
+
+pub trait ScriptStoreRead {
+  fn get_scripts<'store, 'key>(
+    &'store self,
+    script_hashes: impl 'key + IntoIterator<Item = &'key ScriptHash>,
+  ) -> anyhow::Result<impl 'store + Iterator<Item = anyhow::Result<Option<ScriptBuf>>>>;
+
+  fn get_script_by_sha256(&self, script_sha256: &sha256::Hash) -> anyhow::Result<Option<ScriptBuf>>;
+}
+
+pub trait ScriptStoreWrite {
+  fn insert_scripts<'a>(&mut self, entries: impl Iterator<Item = (&'a ScriptHash, &'a ScriptBuf)>);
+}
+
+impl ScriptStoreRead for Store {
+  fn get_scripts<'store, 'key>(
+    &'store self,
+    script_hashes: impl 'key + IntoIterator<Item = &'key ScriptHash>,
+  ) -> anyhow::Result<impl 'store + Iterator<Item = anyhow::Result<Option<ScriptBuf>>>> {
+    let cf = self.db.cf_handle("script_hash_to_script").unwrap();
+
+    let keys = script_hashes
+      .into_iter()
+      .map(|h| ScriptHashCodec.encode_to_vec(h).unwrap())
+      .collect::<Vec<_>>();
+
+    if !keys.is_sorted() {
+      anyhow::bail!("script hashes must be provided in sorted order");
+    }
+
+    Ok(
+      self.db.batched_multi_get_cf(&cf, &keys, true)
+        .into_iter()
+        .map(|res| -> anyhow::Result<_> {
+          Ok(res?.map(|value| ScriptBuf::from_bytes(value.to_vec())))
+        })
+    )
+  }
+
+  fn get_script_by_sha256(&self, script_sha256: &sha256::Hash) -> anyhow::Result<Option<ScriptBuf>> {
+    let cf = self.db.cf_handle("script_sha256_to_script_hash").unwrap();
+    let Some(value) = self.db.get_cf(&cf, script_sha256.as_byte_array())? else {
+      return Ok(None);
+    };
+    let script_hash = ScriptHashCodec.decode(value.as_slice(), &mut 0)?;
+    let Some(script) = self.get_scripts([&script_hash])?.next().transpose()?.flatten() else {
+      anyhow::bail!("missing script of script hash {}", script_hash);
+    };
+    Ok(Some(script))
+  }
+}
+
+impl ScriptStoreWrite for Batch<'_> {
+  fn insert_scripts<'a>(&mut self, entries: impl Iterator<Item = (&'a ScriptHash, &'a ScriptBuf)>) {
+    let cf_script_hash_to_script = self.store.db.cf_handle("script_hash_to_script").unwrap();
+    let cf_script_sha256_to_script_hash = self.store.db.cf_handle("script_sha256_to_script_hash").unwrap();
+
+    for (script_hash, script) in entries {
+      let key_script_hash = ScriptHashCodec.encode_to_vec(script_hash).unwrap();
+      let script_sha256 = sha256::Hash::hash(script.as_bytes());
+      self.batch.put_cf(&cf_script_sha256_to_script_hash, script_sha256.as_byte_array(), &key_script_hash);
+      self.batch.put_cf(&cf_script_hash_to_script, key_script_hash, script.as_bytes());
+    }
+  }
+}
+
+pub fn cf_descriptors(common_opts: &rocksdb::Options) -> Vec<rocksdb::ColumnFamilyDescriptor> {
+  vec![
+    rocksdb::ColumnFamilyDescriptor::new("script_hash_to_script", common_opts.clone()),
+    rocksdb::ColumnFamilyDescriptor::new("script_sha256_to_script_hash", common_opts.clone()),
+  ]
+}
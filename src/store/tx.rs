@@ -0,0 +1,56 @@
+use bitcoin::Txid;
+use byten::{Decode, Encode, Measure, prelude::{EncodeToVec, EncoderToVec as _}, var};
+
+use crate::store::{Batch, BlockHeight, Store, codec::TxidCodec};
+
+pub trait TXStoreRead {
+  fn get_tx_location(&self, txid: &Txid) -> anyhow::Result<Option<TXLocation>>;
+}
+
+pub trait TXStoreWrite {
+  fn insert_txs<'a>(&mut self, entries: impl Iterator<Item = (&'a Txid, &'a TXLocation)>);
+  fn remove_txs<'a>(&mut self, entries: impl Iterator<Item = &'a Txid>);
+}
+
+impl TXStoreRead for Store {
+  fn get_tx_location(&self, txid: &Txid) -> anyhow::Result<Option<TXLocation>> {
+    let cf = self.db.cf_handle("txid_to_location").unwrap();
+    let Some(value) = self.db.get_cf(&cf, TxidCodec.encode_to_vec(txid).unwrap())? else {
+      return Ok(None);
+    };
+    Ok(Some(TXLocation::decode(value.as_slice(), &mut 0)?))
+  }
+}
+
+impl TXStoreWrite for Batch<'_> {
+  fn insert_txs<'a>(&mut self, entries: impl Iterator<Item = (&'a Txid, &'a TXLocation)>) {
+    let cf = self.store.db.cf_handle("txid_to_location").unwrap();
+
+    for (txid, location) in entries {
+      self.batch.put_cf(&cf, TxidCodec.encode_to_vec(txid).unwrap(), location.encode_to_vec().unwrap());
+    }
+  }
+
+  fn remove_txs<'a>(&mut self, entries: impl Iterator<Item = &'a Txid>) {
+    let cf = self.store.db.cf_handle("txid_to_location").unwrap();
+
+    for txid in entries {
+      self.batch.delete_cf(&cf, TxidCodec.encode_to_vec(txid).unwrap());
+    }
+  }
+}
+
+pub fn cf_descriptors(common_opts: &rocksdb::Options) -> Vec<rocksdb::ColumnFamilyDescriptor> {
+  vec![
+    rocksdb::ColumnFamilyDescriptor::new("txid_to_location", common_opts.clone()),
+  ]
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Encode, Decode, Measure)]
+pub struct TXLocation {
+  #[byten(var::U32BE)]
+  pub height: BlockHeight,
+  // index of the transaction within its block
+  #[byten(var::U32BE)]
+  pub position: u32,
+}
@@ -1,9 +1,9 @@
-use bitcoin::{hashes::Hash, Amount, OutPoint, ScriptHash};
+use bitcoin::{hashes::Hash, Amount, OutPoint, ScriptHash, Txid};
 use byten::{Decode, Decoder, Encode, Measure, prelude::{EncodeToVec, EncoderToVec as _}, var};
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use rocksdb::SliceTransform;
 
-use crate::{iter_util::IterExt, store::{Batch, BlockHeight, Store, codec::{AmountCodec, ScriptHashCodec, OutPointCodec}}};
+use crate::{iter_util::IterExt, store::{migration::Migration, Batch, BlockHeight, Seek, Store, codec::{AmountCodec, ScriptHashCodec, OutPointCodec, SpenderCodec, TxidCodec}}};
 
 pub fn cf_descriptors(common_opts: &rocksdb::Options) -> Vec<rocksdb::ColumnFamilyDescriptor> {
   let mut outpoint_to_txo_opts = common_opts.clone();
@@ -29,13 +29,24 @@ pub fn cf_descriptors(common_opts: &rocksdb::Options) -> Vec<rocksdb::ColumnFami
             value: g.value,
             generated_height: g.generated_height,
             spent_height: None,
+            spender: None,
           });
         }
+        TXOUpdate::LegacySpent(s) => {
+          let Some(ref mut state) = state else {
+            panic!("TXOSpent update for missing TXOState");
+          };
+          state.spent_height = Some(s.spent_height);
+        }
         TXOUpdate::Spent(s) => {
           let Some(ref mut state) = state else {
             panic!("TXOSpent update for missing TXOState");
           };
           state.spent_height = Some(s.spent_height);
+          state.spender = Some(TXOSpender {
+            txid: s.spending_txid,
+            vin: s.spending_vin,
+          });
         }
       }
     }
@@ -50,6 +61,9 @@ pub fn cf_descriptors(common_opts: &rocksdb::Options) -> Vec<rocksdb::ColumnFami
   locker_script_hash_and_outpoint_opts_block.set_whole_key_filtering(false);
   locker_script_hash_and_outpoint_opts.set_block_based_table_factory(&locker_script_hash_and_outpoint_opts_block);
 
+  let mut spending_txid_and_vin_opts = common_opts.clone();
+  spending_txid_and_vin_opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(Txid::LEN));
+
   let mut height_and_outpoint_opts = common_opts.clone();
   height_and_outpoint_opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(BlockHeight::BITS as usize / 8));
   let mut height_and_outpoint_opts_block = rocksdb::BlockBasedOptions::default();
@@ -60,8 +74,10 @@ pub fn cf_descriptors(common_opts: &rocksdb::Options) -> Vec<rocksdb::ColumnFami
   vec![
     rocksdb::ColumnFamilyDescriptor::new("outpoint_to_txo_state", outpoint_to_txo_opts),
     rocksdb::ColumnFamilyDescriptor::new("locker_script_hash_and_outpoint", locker_script_hash_and_outpoint_opts.clone()),
+    rocksdb::ColumnFamilyDescriptor::new("locker_script_hash_and_unspent_outpoint", locker_script_hash_and_outpoint_opts.clone()),
     rocksdb::ColumnFamilyDescriptor::new("generated_height_and_outpoint", height_and_outpoint_opts.clone()),
     rocksdb::ColumnFamilyDescriptor::new("spent_height_and_outpoint", height_and_outpoint_opts.clone()),
+    rocksdb::ColumnFamilyDescriptor::new("spending_txid_and_vin", spending_txid_and_vin_opts),
   ]
 }
 
@@ -75,17 +91,35 @@ pub struct TXOGenerated {
   pub generated_height: BlockHeight,
 }
 
+// written before spenders were recorded, may still be pending in older data dirs
+#[derive(Copy, Clone, Encode, Decode, Measure)]
+pub struct TXOLegacySpent {
+  #[byten(var::U32BE)]
+  pub spent_height: BlockHeight,
+}
+
 #[derive(Copy, Clone, Encode, Decode, Measure)]
 pub struct TXOSpent {
   #[byten(var::U32BE)]
   pub spent_height: BlockHeight,
+  #[byten(TxidCodec)]
+  pub spending_txid: Txid,
+  #[byten(var::U32BE)]
+  pub spending_vin: u32,
 }
 
 #[derive(Copy, Clone, Encode, Decode, Measure)]
 #[repr(u8)]
 pub enum TXOUpdate {
   Generated(TXOGenerated) = 1,
-  Spent(TXOSpent) = 2,
+  LegacySpent(TXOLegacySpent) = 2,
+  Spent(TXOSpent) = 3,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct TXOSpender {
+  pub txid: Txid,
+  pub vin: u32,
 }
 
 pub trait TXOStoreRead {
@@ -97,12 +131,44 @@ pub trait TXOStoreRead {
   fn get_locker_script_txos<'store, 'key>(
     &'store self,
     locker_script_hash: &ScriptHash,
+    seek: Seek<OutPoint>,
+  ) -> anyhow::Result<impl 'store + Iterator<Item = anyhow::Result<OutPoint>>>;
+
+  fn get_locker_script_unspent_txos<'store>(
+    &'store self,
+    locker_script_hash: &ScriptHash,
+    seek: Seek<OutPoint>,
+  ) -> anyhow::Result<impl 'store + Iterator<Item = anyhow::Result<OutPoint>>>;
+
+  fn get_generated_outpoints<'store>(
+    &'store self,
+    generated_height: BlockHeight,
   ) -> anyhow::Result<impl 'store + Iterator<Item = anyhow::Result<OutPoint>>>;
+
+  fn get_spent_outpoints<'store>(
+    &'store self,
+    spent_height: BlockHeight,
+  ) -> anyhow::Result<impl 'store + Iterator<Item = anyhow::Result<OutPoint>>>;
+
+  fn get_spent_prevouts<'store>(
+    &'store self,
+    spending_txid: &Txid,
+  ) -> anyhow::Result<impl 'store + Iterator<Item = anyhow::Result<(u32, OutPoint)>>>;
+
+  fn get_tx_txos<'store>(
+    &'store self,
+    txid: &Txid,
+  ) -> anyhow::Result<impl 'store + Iterator<Item = anyhow::Result<(OutPoint, TXOState)>>>;
 }
 
 pub trait TXOStoreWrite {
   fn generated_txos<'data>(&mut self, entries: impl IntoParallelIterator<Item = (&'data OutPoint, &'data TXOGenerated)>);
-  fn spent_txos<'data>(&mut self, entries: impl IntoParallelIterator<Item = (&'data OutPoint, &'data TXOSpent)>);
+  fn spent_txos<'data>(&mut self, entries: impl IntoParallelIterator<Item = (&'data OutPoint, &'data TXOSpent, &'data ScriptHash)>);
+
+  fn revert_generated_txos<'data>(&mut self, entries: impl IntoParallelIterator<Item = (&'data OutPoint, &'data TXOGenerated)>);
+  fn revert_spent_txos<'data>(&mut self, entries: impl IntoParallelIterator<Item = (&'data OutPoint, &'data TXOState)>);
+
+  fn record_spenders<'data>(&mut self, entries: impl IntoParallelIterator<Item = (&'data OutPoint, &'data TXOSpent)>);
 }
 
 impl TXOStoreRead for Store {
@@ -136,11 +202,144 @@ impl TXOStoreRead for Store {
   fn get_locker_script_txos<'store, 'key>(
     &'store self,
     locker_script_hash: &ScriptHash,
+    seek: Seek<OutPoint>,
+  ) -> anyhow::Result<impl 'store + Iterator<Item = anyhow::Result<OutPoint>>> {
+    self.iterate_locker_script_outpoints("locker_script_hash_and_outpoint", locker_script_hash, seek)
+  }
+
+  fn get_locker_script_unspent_txos<'store>(
+    &'store self,
+    locker_script_hash: &ScriptHash,
+    seek: Seek<OutPoint>,
+  ) -> anyhow::Result<impl 'store + Iterator<Item = anyhow::Result<OutPoint>>> {
+    self.iterate_locker_script_outpoints("locker_script_hash_and_unspent_outpoint", locker_script_hash, seek)
+  }
+
+  fn get_generated_outpoints<'store>(
+    &'store self,
+    generated_height: BlockHeight,
+  ) -> anyhow::Result<impl 'store + Iterator<Item = anyhow::Result<OutPoint>>> {
+    let cf = self.db.cf_handle("generated_height_and_outpoint").unwrap();
+    // heights are var-encoded, so they may not fill the fixed-size prefix of this CF
+    let prefix = var::U32BE.encode_to_vec(&generated_height).unwrap();
+
+    let iter = self.db.full_iterator_cf(&cf, rocksdb::IteratorMode::From(&prefix, rocksdb::Direction::Forward));
+    Ok(
+      iter.map(|res| -> anyhow::Result<_> {
+        let (key, _value) = res?;
+        Ok(GeneratedHeightAndOutPoint::decode(key.as_ref(), &mut 0)?)
+      })
+      .take_while(move |key| {
+        match key {
+          Ok(k) => k.generated_height == generated_height,
+          Err(_) => true,
+        }
+      })
+      .map_ok(|k| Ok(k.outpoint))
+    )
+  }
+
+  fn get_spent_outpoints<'store>(
+    &'store self,
+    spent_height: BlockHeight,
   ) -> anyhow::Result<impl 'store + Iterator<Item = anyhow::Result<OutPoint>>> {
-    let cf = self.db.cf_handle("locker_script_hash_and_outpoint").unwrap();
-    let prefix = locker_script_hash.as_byte_array();
+    let cf = self.db.cf_handle("spent_height_and_outpoint").unwrap();
+    // heights are var-encoded, so they may not fill the fixed-size prefix of this CF
+    let prefix = var::U32BE.encode_to_vec(&spent_height).unwrap();
+
+    let iter = self.db.full_iterator_cf(&cf, rocksdb::IteratorMode::From(&prefix, rocksdb::Direction::Forward));
+    Ok(
+      iter.map(|res| -> anyhow::Result<_> {
+        let (key, _value) = res?;
+        Ok(SpentHeightAndOutPoint::decode(key.as_ref(), &mut 0)?)
+      })
+      .take_while(move |key| {
+        match key {
+          Ok(k) => k.spent_height == spent_height,
+          Err(_) => true,
+        }
+      })
+      .map_ok(|k| Ok(k.outpoint))
+    )
+  }
+
+  fn get_spent_prevouts<'store>(
+    &'store self,
+    spending_txid: &Txid,
+  ) -> anyhow::Result<impl 'store + Iterator<Item = anyhow::Result<(u32, OutPoint)>>> {
+    let cf = self.db.cf_handle("spending_txid_and_vin").unwrap();
+    let prefix = spending_txid.as_byte_array();
 
     let iter = self.db.prefix_iterator_cf(&cf, prefix);
+    Ok(
+      iter.map(|res| -> anyhow::Result<_> {
+        let (key, value) = res?;
+        let key = SpendingTxidAndVin::decode(key.as_ref(), &mut 0)?;
+        let prevout = OutPointCodec::Fix.decode(value.as_ref(), &mut 0)?;
+        Ok((key, prevout))
+      })
+      .take_while({
+        let spending_txid = spending_txid.clone();
+        move |entry| {
+          match entry {
+            Ok((k, _)) => k.spending_txid == spending_txid,
+            Err(_) => true,
+          }
+        }
+      })
+      .map_ok(|(k, prevout)| Ok((k.spending_vin, prevout)))
+    )
+  }
+
+  fn get_tx_txos<'store>(
+    &'store self,
+    txid: &Txid,
+  ) -> anyhow::Result<impl 'store + Iterator<Item = anyhow::Result<(OutPoint, TXOState)>>> {
+    let cf = self.db.cf_handle("outpoint_to_txo_state").unwrap();
+    let start = OutPointCodec::Fix.encode_to_vec(&OutPoint { txid: *txid, vout: 0 }).unwrap();
+
+    let iter = self.db.iterator_cf(&cf, rocksdb::IteratorMode::From(&start, rocksdb::Direction::Forward));
+    Ok(
+      iter.map(|res| -> anyhow::Result<_> {
+        let (key, value) = res?;
+        let outpoint = OutPointCodec::Fix.decode(key.as_ref(), &mut 0)?;
+        let state = TXOState::decode(value.as_ref(), &mut 0)?;
+        Ok((outpoint, state))
+      })
+      .take_while({
+        let txid = txid.clone();
+        move |entry| {
+          match entry {
+            Ok((outpoint, _)) => outpoint.txid == txid,
+            Err(_) => true,
+          }
+        }
+      })
+    )
+  }
+}
+
+impl Store {
+  fn iterate_locker_script_outpoints<'store>(
+    &'store self,
+    cf_name: &str,
+    locker_script_hash: &ScriptHash,
+    seek: Seek<OutPoint>,
+  ) -> anyhow::Result<impl 'store + Iterator<Item = anyhow::Result<OutPoint>>> {
+    let cf = self.db.cf_handle(cf_name).unwrap();
+    let start = match seek {
+      Seek::First => locker_script_hash.as_byte_array().to_vec(),
+      Seek::Last => LockerScriptHashAndOutpoint {
+        locker_script_hash: *locker_script_hash,
+        outpoint: OutPoint { txid: Txid::from_byte_array([0xff; 32]), vout: u32::MAX },
+      }.encode_to_vec().unwrap(),
+      Seek::After(outpoint) | Seek::Before(outpoint) => LockerScriptHashAndOutpoint {
+        locker_script_hash: *locker_script_hash,
+        outpoint,
+      }.encode_to_vec().unwrap(),
+    };
+
+    let iter = self.db.iterator_cf(&cf, rocksdb::IteratorMode::From(&start, seek.direction()));
     Ok(
       iter.map(|res| -> anyhow::Result<_> {
         let (key, _value) = res?;
@@ -155,15 +354,52 @@ impl TXOStoreRead for Store {
           }
         }
       })
+      .filter(move |key| {
+        match key {
+          Ok(k) => seek.cursor() != Some(&k.outpoint),
+          Err(_) => true,
+        }
+      })
       .map_ok(|k| Ok(k.outpoint))
     )
   }
+
+  // fills the unspent outpoint index of a data dir created before it existed
+  pub(crate) fn index_unspent_outpoints(&self) -> anyhow::Result<()> {
+    let cf_outpoint_to_txo_state = self.db.cf_handle("outpoint_to_txo_state").unwrap();
+    let cf_locker_script_hash_and_unspent_outpoint = self.db.cf_handle("locker_script_hash_and_unspent_outpoint").unwrap();
+    let chunk_size = 100_000;
+
+    // entries of an interrupted run are written again, so the run needs no resume point
+    let mut batch = Batch {
+      store: self,
+      batch: rocksdb::WriteBatch::default(),
+    };
+    for res in self.db.full_iterator_cf(&cf_outpoint_to_txo_state, rocksdb::IteratorMode::Start) {
+      let (key, value) = res?;
+      let state = TXOState::decode(value.as_ref(), &mut 0)?;
+      if state.spent_height.is_some() {
+        continue;
+      }
+      let key_locker_script_hash_and_unspent_outpoint = LockerScriptHashAndOutpoint {
+        locker_script_hash: state.locker_script_hash,
+        outpoint: OutPointCodec::Fix.decode(key.as_ref(), &mut 0)?,
+      }.encode_to_vec().unwrap();
+      batch.batch.put_cf(&cf_locker_script_hash_and_unspent_outpoint, key_locker_script_hash_and_unspent_outpoint, &[]);
+      if batch.batch.len() >= chunk_size {
+        self.db.write(std::mem::take(&mut batch.batch))?;
+      }
+    }
+    batch.set_migration_pending(Migration::UnspentOutpoints, false);
+    batch.commit()
+  }
 }
 
 impl TXOStoreWrite for Batch<'_> {
   fn generated_txos<'data>(&mut self, entries: impl IntoParallelIterator<Item = (&'data OutPoint, &'data TXOGenerated)>) {
     let cf_outpoint_to_txo_state = self.store.db.cf_handle("outpoint_to_txo_state").unwrap();
     let cf_locker_script_hash_and_outpoint = self.store.db.cf_handle("locker_script_hash_and_outpoint").unwrap();
+    let cf_locker_script_hash_and_unspent_outpoint = self.store.db.cf_handle("locker_script_hash_and_unspent_outpoint").unwrap();
     let cf_generated_height_and_outpoint = self.store.db.cf_handle("generated_height_and_outpoint").unwrap();
 
     let entries = entries
@@ -187,34 +423,147 @@ impl TXOStoreWrite for Batch<'_> {
 
     for (key_outpoint_to_txo_state, value, key_locker_script_hash_and_outpoint, key_generated_height_and_outpoint) in entries {
       self.batch.merge_cf(&cf_outpoint_to_txo_state, key_outpoint_to_txo_state, value);
-      self.batch.put_cf(&cf_locker_script_hash_and_outpoint, key_locker_script_hash_and_outpoint, &[]);
+      self.batch.put_cf(&cf_locker_script_hash_and_outpoint, &key_locker_script_hash_and_outpoint, &[]);
+      self.batch.put_cf(&cf_locker_script_hash_and_unspent_outpoint, key_locker_script_hash_and_outpoint, &[]);
       self.batch.put_cf(&cf_generated_height_and_outpoint, key_generated_height_and_outpoint, &[]);
     }
   }
 
-  fn spent_txos<'data>(&mut self, entries: impl IntoParallelIterator<Item = (&'data OutPoint, &'data TXOSpent)>) {
+  fn spent_txos<'data>(&mut self, entries: impl IntoParallelIterator<Item = (&'data OutPoint, &'data TXOSpent, &'data ScriptHash)>) {
     let cf_outpoint_to_txo_state = self.store.db.cf_handle("outpoint_to_txo_state").unwrap();
+    let cf_locker_script_hash_and_unspent_outpoint = self.store.db.cf_handle("locker_script_hash_and_unspent_outpoint").unwrap();
     let cf_spent_height_and_outpoint = self.store.db.cf_handle("spent_height_and_outpoint").unwrap();
+    let cf_spending_txid_and_vin = self.store.db.cf_handle("spending_txid_and_vin").unwrap();
     let entries = entries
       .into_par_iter()
-      .map(|(outpoint, spent)| {
+      .map(|(outpoint, spent, locker_script_hash)| {
         let key_outpoint_to_txo_state = OutPointCodec::Fix.encode_to_vec(outpoint).unwrap();
         let value = TXOUpdate::Spent(spent.clone()).encode_to_vec().unwrap();
+        let key_locker_script_hash_and_unspent_outpoint = LockerScriptHashAndOutpoint {
+          locker_script_hash: *locker_script_hash,
+          outpoint: *outpoint,
+        }.encode_to_vec().unwrap();
         let key_spent_height_and_outpoint = SpentHeightAndOutPoint {
           spent_height: spent.spent_height,
           outpoint: *outpoint,
         }.encode_to_vec().unwrap();
-        (key_outpoint_to_txo_state, value, key_spent_height_and_outpoint)
+        let key_spending_txid_and_vin = SpendingTxidAndVin {
+          spending_txid: spent.spending_txid,
+          spending_vin: spent.spending_vin,
+        }.encode_to_vec().unwrap();
+        (key_outpoint_to_txo_state, value, key_locker_script_hash_and_unspent_outpoint, key_spent_height_and_outpoint, key_spending_txid_and_vin)
       })
       .collect_vec_list()
       .into_iter()
       .flatten();
 
-    for (key, value, key_spent_height_and_outpoint) in entries {
+    for (key, value, key_locker_script_hash_and_unspent_outpoint, key_spent_height_and_outpoint, key_spending_txid_and_vin) in entries {
+      self.batch.put_cf(&cf_spending_txid_and_vin, key_spending_txid_and_vin, &key);
       self.batch.merge_cf(&cf_outpoint_to_txo_state, key, value);
+      self.batch.delete_cf(&cf_locker_script_hash_and_unspent_outpoint, key_locker_script_hash_and_unspent_outpoint);
       self.batch.put_cf(&cf_spent_height_and_outpoint, key_spent_height_and_outpoint, &[]);
     }
   }
+
+  fn revert_generated_txos<'data>(&mut self, entries: impl IntoParallelIterator<Item = (&'data OutPoint, &'data TXOGenerated)>) {
+    let cf_outpoint_to_txo_state = self.store.db.cf_handle("outpoint_to_txo_state").unwrap();
+    let cf_locker_script_hash_and_outpoint = self.store.db.cf_handle("locker_script_hash_and_outpoint").unwrap();
+    let cf_locker_script_hash_and_unspent_outpoint = self.store.db.cf_handle("locker_script_hash_and_unspent_outpoint").unwrap();
+    let cf_generated_height_and_outpoint = self.store.db.cf_handle("generated_height_and_outpoint").unwrap();
+
+    let entries = entries
+      .into_par_iter()
+      .map(|(outpoint, generated)| {
+        let key_outpoint_to_txo_state = OutPointCodec::Fix.encode_to_vec(outpoint).unwrap();
+        let key_locker_script_hash_and_outpoint = LockerScriptHashAndOutpoint {
+          locker_script_hash: generated.locker_script_hash,
+          outpoint: *outpoint,
+        }.encode_to_vec().unwrap();
+        let key_generated_height_and_outpoint = GeneratedHeightAndOutPoint {
+          generated_height: generated.generated_height,
+          outpoint: *outpoint,
+        }.encode_to_vec().unwrap();
+        (key_outpoint_to_txo_state, key_locker_script_hash_and_outpoint, key_generated_height_and_outpoint)
+      })
+      .collect_vec_list()
+      .into_iter()
+      .flatten();
+
+    for (key_outpoint_to_txo_state, key_locker_script_hash_and_outpoint, key_generated_height_and_outpoint) in entries {
+      self.batch.delete_cf(&cf_outpoint_to_txo_state, key_outpoint_to_txo_state);
+      self.batch.delete_cf(&cf_locker_script_hash_and_outpoint, &key_locker_script_hash_and_outpoint);
+      self.batch.delete_cf(&cf_locker_script_hash_and_unspent_outpoint, key_locker_script_hash_and_outpoint);
+      self.batch.delete_cf(&cf_generated_height_and_outpoint, key_generated_height_and_outpoint);
+    }
+  }
+
+  fn revert_spent_txos<'data>(&mut self, entries: impl IntoParallelIterator<Item = (&'data OutPoint, &'data TXOState)>) {
+    let cf_outpoint_to_txo_state = self.store.db.cf_handle("outpoint_to_txo_state").unwrap();
+    let cf_locker_script_hash_and_unspent_outpoint = self.store.db.cf_handle("locker_script_hash_and_unspent_outpoint").unwrap();
+    let cf_spent_height_and_outpoint = self.store.db.cf_handle("spent_height_and_outpoint").unwrap();
+    let cf_spending_txid_and_vin = self.store.db.cf_handle("spending_txid_and_vin").unwrap();
+    let entries = entries
+      .into_par_iter()
+      .filter_map(|(outpoint, state)| {
+        let spent_height = state.spent_height?;
+        let key_outpoint_to_txo_state = OutPointCodec::Fix.encode_to_vec(outpoint).unwrap();
+        let value = TXOState {
+          spent_height: None,
+          spender: None,
+          ..*state
+        }.encode_to_vec().unwrap();
+        let key_locker_script_hash_and_unspent_outpoint = LockerScriptHashAndOutpoint {
+          locker_script_hash: state.locker_script_hash,
+          outpoint: *outpoint,
+        }.encode_to_vec().unwrap();
+        let key_spent_height_and_outpoint = SpentHeightAndOutPoint {
+          spent_height,
+          outpoint: *outpoint,
+        }.encode_to_vec().unwrap();
+        let key_spending_txid_and_vin = state.spender.map(|spender| SpendingTxidAndVin {
+          spending_txid: spender.txid,
+          spending_vin: spender.vin,
+        }.encode_to_vec().unwrap());
+        Some((key_outpoint_to_txo_state, value, key_locker_script_hash_and_unspent_outpoint, key_spent_height_and_outpoint, key_spending_txid_and_vin))
+      })
+      .collect_vec_list()
+      .into_iter()
+      .flatten();
+
+    for (key, value, key_locker_script_hash_and_unspent_outpoint, key_spent_height_and_outpoint, key_spending_txid_and_vin) in entries {
+      // a put discards the merge operands stacked on the key so far
+      self.batch.put_cf(&cf_outpoint_to_txo_state, key, value);
+      self.batch.put_cf(&cf_locker_script_hash_and_unspent_outpoint, key_locker_script_hash_and_unspent_outpoint, &[]);
+      self.batch.delete_cf(&cf_spent_height_and_outpoint, key_spent_height_and_outpoint);
+      if let Some(key_spending_txid_and_vin) = key_spending_txid_and_vin {
+        self.batch.delete_cf(&cf_spending_txid_and_vin, key_spending_txid_and_vin);
+      }
+    }
+  }
+
+  fn record_spenders<'data>(&mut self, entries: impl IntoParallelIterator<Item = (&'data OutPoint, &'data TXOSpent)>) {
+    let cf_outpoint_to_txo_state = self.store.db.cf_handle("outpoint_to_txo_state").unwrap();
+    let cf_spending_txid_and_vin = self.store.db.cf_handle("spending_txid_and_vin").unwrap();
+    let entries = entries
+      .into_par_iter()
+      .map(|(outpoint, spent)| {
+        let key_outpoint_to_txo_state = OutPointCodec::Fix.encode_to_vec(outpoint).unwrap();
+        let value = TXOUpdate::Spent(spent.clone()).encode_to_vec().unwrap();
+        let key_spending_txid_and_vin = SpendingTxidAndVin {
+          spending_txid: spent.spending_txid,
+          spending_vin: spent.spending_vin,
+        }.encode_to_vec().unwrap();
+        (key_outpoint_to_txo_state, value, key_spending_txid_and_vin)
+      })
+      .collect_vec_list()
+      .into_iter()
+      .flatten();
+
+    for (key, value, key_spending_txid_and_vin) in entries {
+      self.batch.put_cf(&cf_spending_txid_and_vin, key_spending_txid_and_vin, &key);
+      self.batch.merge_cf(&cf_outpoint_to_txo_state, key, value);
+    }
+  }
 }
 
 #[derive(Clone, Copy, Encode, Decode, Measure)]
@@ -227,6 +576,16 @@ pub struct TXOState {
   pub generated_height: BlockHeight,
   #[byten(var::Option::<var::U32BE>::default())]
   pub spent_height: Option<BlockHeight>,
+  #[byten(SpenderCodec)]
+  pub spender: Option<TXOSpender>,
+}
+
+#[derive(Encode, Decode, Measure)]
+pub struct SpendingTxidAndVin {
+  #[byten(TxidCodec)]
+  pub spending_txid: Txid,
+  #[byten(var::U32BE)]
+  pub spending_vin: u32,
 }
 
 #[derive(Encode, Decode, Measure)]
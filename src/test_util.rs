@@ -0,0 +1,121 @@
+use std::{path::PathBuf, sync::Arc};
+
+use bitcoin::{absolute::LockTime, block, hashes::Hash as _, transaction, Amount, Block, BlockHash, CompactTarget, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxMerkleNode, TxOut, WPubkeyHash, Witness};
+
+use crate::{scanner::{batch::Batch, events::ConnectedBlocks}, store::{self, block::BlockStoreRead as _, BlockHeight, Store}};
+
+// A store in a temp dir, filled by the scanner from synthetic blocks
+pub struct TestStore {
+  store: Option<Arc<Store>>,
+  path: PathBuf,
+}
+
+impl TestStore {
+  pub fn open(name: &str) -> Self {
+    let path = std::env::temp_dir().join(format!("scanner-test-{}-{}", name, std::process::id()));
+    _ = std::fs::remove_dir_all(&path);
+    let store = Store::open(path.to_str().unwrap()).unwrap();
+    Self { store: Some(Arc::new(store)), path }
+  }
+
+  pub fn store(&self) -> &Arc<Store> {
+    self.store.as_ref().unwrap()
+  }
+
+  // closes the store and opens it again, like a restart, running the migrations marked pending
+  pub fn reopen(&mut self) {
+    drop(self.store.take());
+    self.store = Some(Arc::new(Store::open(self.path.to_str().unwrap()).unwrap()));
+  }
+
+  // appends a block on the store tip, the first of `txs` being its coinbase
+  pub fn connect_block(&self, txs: Vec<Transaction>) -> ConnectedBlocks {
+    let (height, prev_blockhash) = match self.store().get_tip_block().unwrap() {
+      Some((tip_height, tip_hash)) => (tip_height + 1, tip_hash),
+      None => (0, BlockHash::all_zeros()),
+    };
+
+    let block = block(height, prev_blockhash, txs);
+
+    let mut batch = store::Batch {
+      store: self.store(),
+      batch: rocksdb::WriteBatch::default(),
+    };
+    let connected = Batch::build(height, vec![block]).unwrap().write(&mut batch).unwrap();
+    batch.commit().unwrap();
+    connected
+  }
+}
+
+impl Drop for TestStore {
+  fn drop(&mut self) {
+    drop(self.store.take());
+    _ = std::fs::remove_dir_all(&self.path);
+  }
+}
+
+// locker script of the TXOs a test follows, it has an address
+pub fn script() -> ScriptBuf {
+  ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([1; 20]))
+}
+
+pub fn other_script() -> ScriptBuf {
+  ScriptBuf::from_bytes(vec![0x52])
+}
+
+// a block at `height` on top of `prev_blockhash`, the first of `txs` being its coinbase
+pub fn block(height: BlockHeight, prev_blockhash: BlockHash, txs: Vec<Transaction>) -> Block {
+  let mut block = Block {
+    header: block::Header {
+      version: block::Version::ONE,
+      prev_blockhash,
+      merkle_root: TxMerkleNode::all_zeros(),
+      time: 1_231_006_505 + height * 600,
+      bits: CompactTarget::from_consensus(0x207fffff),
+      nonce: 0,
+    },
+    txdata: txs,
+  };
+  block.header.merkle_root = block.compute_merkle_root().unwrap();
+  block
+}
+
+// coinbases with the same tag and outputs share their txid, like the BIP30 duplicates
+pub fn coinbase(tag: i64, outputs: &[(ScriptBuf, u64)]) -> Transaction {
+  Transaction {
+    version: transaction::Version::TWO,
+    lock_time: LockTime::ZERO,
+    input: vec![TxIn {
+      previous_output: OutPoint::null(),
+      script_sig: bitcoin::script::Builder::new().push_int(tag).push_int(0).into_script(),
+      sequence: Sequence::MAX,
+      witness: Witness::new(),
+    }],
+    output: tx_outs(outputs),
+  }
+}
+
+pub fn tx(inputs: &[OutPoint], outputs: &[(ScriptBuf, u64)]) -> Transaction {
+  Transaction {
+    version: transaction::Version::TWO,
+    lock_time: LockTime::ZERO,
+    input: inputs.iter().map(|previous_output| TxIn {
+      previous_output: *previous_output,
+      script_sig: ScriptBuf::new(),
+      sequence: Sequence::MAX,
+      witness: Witness::new(),
+    }).collect(),
+    output: tx_outs(outputs),
+  }
+}
+
+fn tx_outs(outputs: &[(ScriptBuf, u64)]) -> Vec<TxOut> {
+  outputs.iter().map(|(script_pubkey, value)| TxOut {
+    value: Amount::from_sat(*value),
+    script_pubkey: script_pubkey.clone(),
+  }).collect()
+}
+
+pub fn outpoint(tx: &Transaction, vout: u32) -> OutPoint {
+  OutPoint { txid: tx.compute_txid(), vout }
+}